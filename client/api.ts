@@ -1,5 +1,18 @@
+// Operator or admin PIN, sent with every control request
+const getToken = () => localStorage.getItem("token")
+
+const setToken = (token: string | null) => {
+    if (token) {
+        localStorage.setItem("token", token)
+    } else {
+        localStorage.removeItem("token")
+    }
+}
+
 const post = async (url: string) => {
-    return fetch(url, { method: "POST" })
+    const token = getToken()
+    const headers: HeadersInit = token ? { Authorization: `Bearer ${token}` } : {}
+    return fetch(url, { method: "POST", headers })
 }
 
 const start = () => {
@@ -14,6 +27,10 @@ const reset = () => {
     post("/api/reset_timer")
 }
 
+const clearFaults = () => {
+    post("/api/clear_faults")
+}
+
 const dispatchEvent = (name: string, data: object) => {
     window.dispatchEvent(new CustomEvent(name, {detail: data}))
 }
@@ -23,21 +40,114 @@ export type Timer = {
     startedAt: number | null,
     stoppedAt: number | null,
     countdown: number,
-    state: "Reset" | "CountingDown" | "Running" | "Stopped",
+    timeLimit: number | null,
+    state: "Reset" | "CountingDown" | "Running" | "Stopped" | "FalseStart" | "Dnf",
     formatted: string,
+    athlete: Athlete | null,
+    splits: number[],
+}
+
+export type Athlete = {
+    id: number,
+    name: string,
+    bib: number,
+    category: string,
+}
+
+export type KeyAction = "start" | "reset" | { stopLane: number } | "toggleDisplay" | "toggleDebug"
+
+export type KeyBinding = {
+    key: string,
+    action: KeyAction,
+}
+
+export type ButtonFilter = {
+    ioBankNum: number,
+    debounceMs: number,
+    minHoldMs: number,
+    ignoreAfterStopMs: number,
+}
+
+export type SerialSettings = {
+    port: string,
+    baud: number,
+    formats: { pattern: string, pressed: boolean }[],
 }
 
 export type Settings = {
     countdown: number,
+    laneCountdowns: (number | null)[],
+    laneMode: "synchronized" | "independent",
     fullscreen: boolean,
     showDebug: boolean,
+    lanes: number,
+    laneButtons: (number | null)[],
+    splitButtons: (number | null)[],
+    falseStartDetection: boolean,
+    startMatButtons: (number | null)[],
+    startButtons: number[],
+    resetButtons: number[],
+    displayButtons: number[],
+    debugButtons: number[],
+    keyBindings: KeyBinding[],
+    buttonFilters: ButtonFilter[],
+    serial: SerialSettings,
+    timeLimit: number | null,
+    requireButtons: boolean,
 }
 
-type TimersEvent = {
+export type ButtonStatus = {
+    connected: boolean,
+    changedAt: string | null,
+    lastEventAt: string | null,
+}
+
+export type Info = {
+    ips: string[],
+    numberDisplays: number,
+    config: object,
+    buttons: ButtonStatus,
+}
+
+export type Fault = {
+    kind: "Overcurrent" | "CriticalError" | "TransportError",
+    ioBankNum: number,
+    at: string,
+}
+
+export type HardwareHealth = {
+    faults: Fault[],
+    faultCount: number,
+}
+
+// Every event payload carries the schema version of src/schema.rs
+const SCHEMA_VERSION = 1
+
+type Versioned = {
+    version: number,
+}
+
+type TimersEvent = Versioned & {
     now: number,
     timers: Timer[],
 }
 
+type InfoEvent = Versioned & {
+    info: Info,
+}
+
+type VisibilityEvent = Versioned & {
+    visible: boolean,
+}
+
+const parse = <T extends Versioned>(data: string): T => {
+    const event = JSON.parse(data) as T
+    if (event.version !== SCHEMA_VERSION) {
+        console.warn(`Unexpected event schema version ${event.version}, expected ${SCHEMA_VERSION}`)
+    }
+    return event
+}
+
 const connect = () => {
 
     const startEventSource = () => {
@@ -72,7 +182,7 @@ const connect = () => {
         })
 
         source.addEventListener("syncTimers", e => {
-            const data = JSON.parse(e.data) as TimersEvent
+            const data = parse<TimersEvent>(e.data)
             const offset = Date.now() - data.now
             const timers: Timer[] = data.timers.map(timer => {
                 return {
@@ -85,10 +195,22 @@ const connect = () => {
         })
 
         source.addEventListener("syncSettings", e => {
-            const settings = JSON.parse(e.data).settings as Settings
+            const settings = parse<Versioned & { settings: Settings }>(e.data).settings
             console.log("syncSettings", settings)
         })
 
+        source.addEventListener("syncInfo", e => {
+            dispatchEvent("infoUpdate", parse<InfoEvent>(e.data).info)
+        })
+
+        source.addEventListener("setDisplay", e => {
+            dispatchEvent("displayChanged", parse<VisibilityEvent>(e.data))
+        })
+
+        source.addEventListener("syncHealth", e => {
+            dispatchEvent("healthUpdate", parse<Versioned & { health: HardwareHealth }>(e.data).health)
+        })
+
         source.addEventListener("ping", e => {
             lastPing = Date.now()
         })
@@ -159,6 +281,8 @@ const Api =  {
     start,
     stop,
     reset,
+    clearFaults,
+    setToken,
     isConnected: false,
 }
 
@@ -1,14 +1,16 @@
 import React, { useState, useEffect, useCallback } from "react"
-import Api from '../api'
+import Api, { HardwareHealth, Info } from '../api'
 
 type NotificationProps = {
   message: string,
+  onDismiss?: () => void,
 }
 
 const Notification: React.FC<NotificationProps> = (props: NotificationProps) => {
   return (
     <div className="p-4 mb-4 text-sm text-red-800 rounded-lg bg-red-50 dark:bg-gray-800 dark:text-red-400" role="alert">
       {props.message}
+      {props.onDismiss ? <button className="ml-4 font-medium underline" onClick={props.onDismiss}>Clear</button> : null}
     </div>
   )
 }
@@ -28,13 +30,53 @@ const useConnected = () => {
   return connected
 }
 
+const useHealth = () => {
+  const [health, setHealth] = useState<HardwareHealth | null>(null)
+
+  const callback = useCallback((e: Event) => {
+    setHealth((e as CustomEvent<HardwareHealth>).detail)
+  }, [setHealth])
+
+  useEffect(() => {
+    window.addEventListener("healthUpdate", callback)
+    return () => window.removeEventListener("healthUpdate", callback)
+  }, [callback])
+
+  return health
+}
+
+const useButtonsConnected = () => {
+  // Unknown until the first syncInfo, which shouldn't flash a warning
+  const [connected, setConnected] = useState(true)
+
+  const callback = useCallback((e: Event) => {
+    setConnected((e as CustomEvent<Info>).detail.buttons.connected)
+  }, [setConnected])
+
+  useEffect(() => {
+    window.addEventListener("infoUpdate", callback)
+    return () => window.removeEventListener("infoUpdate", callback)
+  }, [callback])
+
+  return connected
+}
+
+const describeFault = (health: HardwareHealth) => {
+  const fault = health.faults[health.faults.length - 1]
+  const at = new Date(fault.at).toLocaleTimeString()
+  return `Hardware fault: ${fault.kind} on io bank ${fault.ioBankNum} at ${at} (${health.faultCount} total)`
+}
+
 const Notifications: React.FC = () => {
   const connected = useConnected()
+  const health = useHealth()
+  const buttonsConnected = useButtonsConnected()
 
-  const notifications = []
   return (
     <div>
       {connected ? null : <Notification message="Connection lost. Reconnecting..."/>}
+      {connected && !buttonsConnected ? <Notification message="Buttons offline. Lanes can only be controlled from here."/> : null}
+      {health && health.faults.length > 0 ? <Notification message={describeFault(health)} onDismiss={Api.clearFaults}/> : null}
     </div>
   )
 }
@@ -1,13 +1,18 @@
-use std::env;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
+use crate::config::config_path;
+
+#[cfg(feature = "display")]
 pub const FONT: &[u8] = include_bytes!("../static/Inconsolata-Medium.ttf");
+#[cfg(feature = "display")]
 pub const BEEP1: &[u8] = include_bytes!("../static/beep1.wav");
+#[cfg(feature = "display")]
 pub const BEEP2: &[u8] = include_bytes!("../static/beep2.wav");
+#[cfg(feature = "display")]
 pub const BUZZER: &[u8] = include_bytes!("../static/buzzer.mp3");
+#[cfg(feature = "display")]
+pub const FALSE_START: &[u8] = include_bytes!("../static/false_start.wav");
 
 pub fn get_background_path() -> PathBuf {
-    let home = env::var("HOME").unwrap();
-    let home_path = Path::new(&home);
-    home_path.join(".config/ninja-timer/bg.png")
+    config_path("bg.png")
 }
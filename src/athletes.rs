@@ -0,0 +1,113 @@
+use std::io::{ErrorKind, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Serialize, Deserialize};
+
+use crate::config::{config_path, move_aside};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Athlete {
+    pub id: u64,
+    pub name: String,
+    pub bib: u32,
+    pub category: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Roster {
+    next_id: u64,
+    athletes: Vec<Athlete>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Roster {
+    // Loads the stored athletes, starting empty if there aren't any yet
+    pub fn open() -> Self {
+        match Self::load() {
+            Ok(mut roster) => {
+                roster.path = Some(Self::get_path());
+                roster
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Self {
+                path: Some(Self::get_path()),
+                ..Self::default()
+            },
+            Err(e) => {
+                eprintln!("Couldn't load athletes: {}", e);
+                // Keep the old athletes around instead of overwriting them with the next one added
+                match move_aside(&Self::get_path()) {
+                    Ok(path) => {
+                        eprintln!("Moved the unreadable athletes to {}", path.display());
+                        Self {
+                            path: Some(Self::get_path()),
+                            ..Self::default()
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Couldn't move the athletes aside, changes won't be saved: {}", e);
+                        Self::default()
+                    }
+                }
+            }
+        }
+    }
+
+    fn load() -> Result<Self> {
+        let roster_string = fs::read_to_string(Self::get_path())?;
+        serde_json::from_str(&roster_string).map_err(Into::into)
+    }
+
+    // Does nothing for a roster that wasn't opened from disk
+    pub fn save(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let json = serde_json::to_string(self)?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, json)
+    }
+
+    pub fn athletes(&self) -> &[Athlete] {
+        &self.athletes
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Athlete> {
+        self.athletes.iter().find(|athlete| athlete.id == id)
+    }
+
+    pub fn add(&mut self, name: String, bib: u32, category: String) -> &Athlete {
+        self.athletes.push(Athlete {
+            id: self.next_id,
+            name,
+            bib,
+            category,
+        });
+        self.next_id += 1;
+
+        self.athletes.last().unwrap()
+    }
+
+    pub fn update(&mut self, id: u64, name: String, bib: u32, category: String) -> Option<&Athlete> {
+        let athlete = self.athletes.iter_mut().find(|athlete| athlete.id == id)?;
+        athlete.name = name;
+        athlete.bib = bib;
+        athlete.category = category;
+
+        Some(athlete)
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Athlete> {
+        let index = self.athletes.iter().position(|athlete| athlete.id == id)?;
+        Some(self.athletes.remove(index))
+    }
+
+    fn get_path() -> PathBuf {
+        config_path("athletes.json")
+    }
+}
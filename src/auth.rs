@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use actix_web::{web, http::{header::{self, HeaderMap}, Method}};
+
+use crate::settings::Settings;
+use crate::state::InputEvent;
+
+// Access levels of the web API, each role can do everything the roles below it can
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    // Watches the timers, e.g. a phone in the audience
+    Spectator,
+    // Runs the competition: starts, stops and resets timers, manages athletes
+    Operator,
+    // Changes settings and the background
+    Admin,
+}
+
+// PINs from the settings, without any PINs the API stays open like before
+#[derive(Debug, Clone, Default)]
+pub struct Auth {
+    operator_pin: Option<String>,
+    admin_pin: Option<String>,
+}
+
+impl Auth {
+    pub fn from_settings(settings: &Settings) -> Self {
+        let pin = |pin: &Option<String>| pin.clone().filter(|pin| !pin.is_empty());
+
+        Auth {
+            operator_pin: pin(&settings.operator_pin),
+            admin_pin: pin(&settings.admin_pin),
+        }
+    }
+
+    pub fn role(&self, token: Option<&str>) -> Role {
+        let matches = |pin: &Option<String>| pin.is_some() && pin.as_deref() == token;
+
+        if matches(&self.admin_pin) {
+            return Role::Admin;
+        }
+
+        // Without an admin PIN the operators administer the installation as well
+        if self.admin_pin.is_none() && (self.operator_pin.is_none() || matches(&self.operator_pin)) {
+            return Role::Admin;
+        }
+
+        if matches(&self.operator_pin) {
+            return Role::Operator;
+        }
+
+        Role::Spectator
+    }
+}
+
+// The PIN is sent as "Authorization: Bearer <pin>", or as ?token=<pin> by EventSource and WebSocket clients
+pub fn request_token(headers: &HeaderMap, query_string: &str) -> Option<String> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    if let Some(token) = bearer {
+        return Some(token.trim().to_string());
+    }
+
+    web::Query::<HashMap<String, String>>::from_query(query_string)
+        .ok()
+        .and_then(|query| query.get("token").cloned())
+}
+
+// Role needed for a route, anything not listed here is reserved for admins
+pub fn required_role(method: &Method, path: &str) -> Role {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (method.as_str(), segments.as_slice()) {
+        // The web client itself
+        (_, [first, ..]) if *first != "api" => Role::Spectator,
+        ("GET", ["api", "events" | "info" | "runs" | "athletes"])
+        | ("GET", ["api", "runs", "export"])
+        | ("POST", ["api", "request_sync"]) => Role::Spectator,
+        ("GET", ["api", "ws"])
+        | ("POST", ["api", "start_timer" | "stop_timers" | "reset_timer" | "toggle_display" | "toggle_leaderboard" | "assign_athlete" | "athletes" | "clear_faults"])
+        | ("POST", ["api", "start_timer" | "stop_timer" | "reset_timer" | "split", _])
+        | ("POST" | "DELETE", ["api", "athletes", _])
+        | ("POST" | "DELETE", ["api", "competition"]) => Role::Operator,
+        _ => Role::Admin,
+    }
+}
+
+// Role needed to send an event over the WebSocket control channel
+pub fn required_role_for_event(event: &InputEvent) -> Role {
+    match event {
+        InputEvent::RequestSync => Role::Spectator,
+        InputEvent::StartTimers
+        | InputEvent::StartTimer(_)
+        | InputEvent::ResetTimer(_)
+        | InputEvent::StopTimer(_)
+        | InputEvent::StopTimers
+        | InputEvent::Split(_)
+        | InputEvent::ResetTimers
+        | InputEvent::SetButtonState(_, _, _)
+        | InputEvent::AssignAthlete(_, _)
+        | InputEvent::StartCompetition(_)
+        | InputEvent::EndCompetition
+        | InputEvent::ToggleDisplay
+        | InputEvent::ToggleLeaderboard
+        | InputEvent::ClearFaults => Role::Operator,
+        InputEvent::SetDebug(_)
+        | InputEvent::SetCountdown(_)
+        | InputEvent::SetLaneCountdown(_, _)
+        | InputEvent::SetLaneMode(_)
+        | InputEvent::SetLanes(_)
+        | InputEvent::SetLaneButton(_, _)
+        | InputEvent::SetSplitButton(_, _)
+        | InputEvent::SetStartMatButton(_, _)
+        | InputEvent::SetRoleButtons(_, _)
+        | InputEvent::SetKeyBindings(_)
+        | InputEvent::SetSerial(_)
+        | InputEvent::SetButtonFilters(_)
+        | InputEvent::SetFalseStartDetection(_)
+        | InputEvent::SetRequireButtons(_)
+        | InputEvent::SetTimeLimit(_)
+        | InputEvent::SetPins(_, _)
+        | InputEvent::ReloadBackground
+        | InputEvent::ToggleDebug
+        | InputEvent::ReportFault(_, _)
+        | InputEvent::SetButtonsConnected(_) => Role::Admin,
+        #[cfg(feature = "display")]
+        InputEvent::PressKey(_, _) => Role::Admin,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(operator_pin: Option<&str>, admin_pin: Option<&str>) -> Auth {
+        let settings = Settings {
+            operator_pin: operator_pin.map(String::from),
+            admin_pin: admin_pin.map(String::from),
+            ..Settings::default()
+        };
+
+        Auth::from_settings(&settings)
+    }
+
+    #[test]
+    fn without_pins_everyone_is_admin() {
+        assert_eq!(auth(None, None).role(None), Role::Admin);
+        assert_eq!(auth(Some(""), None).role(None), Role::Admin);
+    }
+
+    #[test]
+    fn pins_grant_their_role() {
+        let auth = auth(Some("1234"), Some("9999"));
+
+        assert_eq!(auth.role(None), Role::Spectator);
+        assert_eq!(auth.role(Some("0000")), Role::Spectator);
+        assert_eq!(auth.role(Some("1234")), Role::Operator);
+        assert_eq!(auth.role(Some("9999")), Role::Admin);
+    }
+
+    #[test]
+    fn operator_pin_alone_protects_admin_routes() {
+        let auth = auth(Some("1234"), None);
+
+        assert_eq!(auth.role(None), Role::Spectator);
+        assert_eq!(auth.role(Some("1234")), Role::Admin);
+    }
+
+    #[test]
+    fn routes_require_roles() {
+        assert_eq!(required_role(&Method::GET, "/index.html"), Role::Spectator);
+        assert_eq!(required_role(&Method::GET, "/api/events"), Role::Spectator);
+        assert_eq!(required_role(&Method::POST, "/api/stop_timer/1"), Role::Operator);
+        assert_eq!(required_role(&Method::GET, "/api/ws"), Role::Operator);
+        assert_eq!(required_role(&Method::POST, "/api/set_countdown"), Role::Admin);
+        assert_eq!(required_role(&Method::DELETE, "/api/runs"), Role::Admin);
+        assert_eq!(required_role(&Method::POST, "/api/unknown"), Role::Admin);
+    }
+}
@@ -13,6 +13,7 @@ use actix_web::{
 };
 use futures_util::Stream;
 use parking_lot::Mutex;
+use serde_json::json;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
 pub struct Broadcaster {
@@ -21,6 +22,7 @@ pub struct Broadcaster {
 
 struct BroadcasterInner {
     clients: Vec<Sender<Bytes>>,
+    ws_clients: Vec<Sender<String>>,
 }
 
 impl Broadcaster {
@@ -29,6 +31,7 @@ impl Broadcaster {
         let me = Data::new(Broadcaster {
             inner: Mutex::new(BroadcasterInner {
                 clients: Vec::new(),
+                ws_clients: Vec::new(),
             }),
         });
 
@@ -61,6 +64,7 @@ impl Broadcaster {
             }
         }
         inner.clients = ok_clients;
+        inner.ws_clients.retain(|client| !client.is_closed());
     }
 
     pub fn new_client(&self) -> Client {
@@ -74,6 +78,16 @@ impl Broadcaster {
         Client(rx)
     }
 
+    // WebSocket clients get every event as a JSON message instead of an SSE frame
+    pub fn new_ws_client(&self) -> Receiver<String> {
+        let (tx, rx) = channel(100);
+
+        let mut inner = self.inner.lock();
+        inner.ws_clients.push(tx);
+
+        rx
+    }
+
     pub fn send_data(&self, msg: &str) {
         let msg = Bytes::from(["data: ", msg, "\n\n"].concat());
 
@@ -84,12 +98,19 @@ impl Broadcaster {
     }
 
     pub fn send(&self, event: &str, msg: &str) {
+        let ws_msg = match serde_json::from_str::<serde_json::Value>(msg) {
+            Ok(data) => json!({ "event": event, "data": data }),
+            Err(_) => json!({ "event": event, "data": msg }),
+        }.to_string();
         let msg = Bytes::from(format!("event: {}\ndata: {}\n\n", event, msg));
 
         let inner = self.inner.lock();
         for client in inner.clients.iter() {
             client.clone().try_send(msg.clone()).unwrap_or(());
         }
+        for client in inner.ws_clients.iter() {
+            client.try_send(ws_msg.clone()).unwrap_or(());
+        }
     }
 }
 
@@ -1,6 +1,8 @@
 use cfg_aliases::cfg_aliases;
 
 fn main() {
+    println!("cargo:rustc-check-cfg=cfg(raspi)");
+
     // Setup cfg aliases
     cfg_aliases! {
         // Platforms
@@ -0,0 +1,61 @@
+use std::fmt::Debug;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+
+#[cfg(test)]
+use std::{sync::Mutex, time::Duration};
+
+// Source of time for the timers and the state machine, swapped out in tests
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+    fn utc_now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+// Clock that only moves when advanced by hand
+#[cfg(test)]
+#[derive(Debug)]
+pub struct ManualClock {
+    instant: Instant,
+    utc: DateTime<Utc>,
+    elapsed: Mutex<Duration>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            instant: Instant::now(),
+            utc: Utc::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.instant + *self.elapsed.lock().unwrap()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        self.utc + chrono::Duration::from_std(*self.elapsed.lock().unwrap()).unwrap()
+    }
+}
@@ -0,0 +1,267 @@
+use std::io::{ErrorKind, Result};
+use std::fs;
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::config::{config_path, move_aside};
+use crate::athletes::Athlete;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Heat {
+    pub round: u32,
+    // Athlete id for each lane, None leaves the lane empty
+    pub lanes: Vec<Option<u64>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompetitionResult {
+    pub athlete: Athlete,
+    pub round: u32,
+    pub millis: i128,
+    #[serde(default)]
+    pub dnf: bool,
+}
+
+impl CompetitionResult {
+    // Finished runs always rank ahead of runs that hit the time limit
+    fn sort_key(&self) -> (bool, i128) {
+        (self.dnf, self.millis)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    pub athlete: Athlete,
+    pub millis: i128,
+    pub dnf: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryStandings {
+    pub category: String,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Leaderboard {
+    pub current_heat: Option<usize>,
+    pub heats: Vec<Heat>,
+    pub categories: Vec<CategoryStandings>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Competition {
+    heats: Vec<Heat>,
+    current_heat: usize,
+    results: Vec<CompetitionResult>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Competition {
+    // Replaces the start list and clears the results of a previous competition
+    pub fn start(&mut self, heats: Vec<Heat>) {
+        self.heats = heats;
+        self.current_heat = 0;
+        self.results.clear();
+    }
+
+    pub fn end(&mut self) {
+        self.start(vec![]);
+    }
+
+    // Loads the stored competition, starting empty if there isn't one yet
+    pub fn open() -> Self {
+        match Self::load() {
+            Ok(mut competition) => {
+                competition.path = Some(Self::get_path());
+                competition
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Self {
+                path: Some(Self::get_path()),
+                ..Self::default()
+            },
+            Err(e) => {
+                eprintln!("Couldn't load competition: {}", e);
+                // Keep the old start list and results around instead of overwriting them
+                match move_aside(&Self::get_path()) {
+                    Ok(path) => {
+                        eprintln!("Moved the unreadable competition to {}", path.display());
+                        Self {
+                            path: Some(Self::get_path()),
+                            ..Self::default()
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Couldn't move the competition aside, changes won't be saved: {}", e);
+                        Self::default()
+                    }
+                }
+            }
+        }
+    }
+
+    fn load() -> Result<Self> {
+        let competition_string = fs::read_to_string(Self::get_path())?;
+        serde_json::from_str(&competition_string).map_err(Into::into)
+    }
+
+    // Does nothing for a competition that wasn't opened from disk
+    pub fn save(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let json = serde_json::to_string(self)?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, json)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.current_heat < self.heats.len()
+    }
+
+    pub fn current_heat(&self) -> Option<&Heat> {
+        self.heats.get(self.current_heat)
+    }
+
+    // Moves to the next heat, returns None once the start list is exhausted
+    pub fn advance(&mut self) -> Option<&Heat> {
+        if self.is_active() {
+            self.current_heat += 1;
+        }
+
+        self.current_heat()
+    }
+
+    pub fn record(&mut self, athlete: Athlete, millis: i128, dnf: bool) {
+        let round = match self.current_heat() {
+            Some(heat) => heat.round,
+            None => return,
+        };
+
+        self.results.push(CompetitionResult {
+            athlete,
+            round,
+            millis,
+            dnf,
+        });
+    }
+
+    // Ranks every athlete by their best result within their category
+    pub fn leaderboard(&self) -> Leaderboard {
+        let mut best: BTreeMap<&str, BTreeMap<u64, &CompetitionResult>> = BTreeMap::new();
+
+        for result in &self.results {
+            let category = best.entry(&result.athlete.category).or_default();
+            let entry = category.entry(result.athlete.id).or_insert(result);
+
+            if result.sort_key() < entry.sort_key() {
+                *entry = result;
+            }
+        }
+
+        let categories = best
+            .into_iter()
+            .map(|(category, results)| {
+                let mut results: Vec<_> = results.into_values().collect();
+                results.sort_by_key(|result| result.sort_key());
+
+                let mut entries: Vec<LeaderboardEntry> = vec![];
+                for (i, result) in results.into_iter().enumerate() {
+                    let rank = match entries.last() {
+                        Some(last) if (last.dnf, last.millis) == result.sort_key() => last.rank,
+                        _ => i + 1,
+                    };
+
+                    entries.push(LeaderboardEntry {
+                        rank,
+                        athlete: result.athlete.clone(),
+                        millis: result.millis,
+                        dnf: result.dnf,
+                    });
+                }
+
+                CategoryStandings {
+                    category: category.to_string(),
+                    entries,
+                }
+            })
+            .collect();
+
+        Leaderboard {
+            current_heat: if self.is_active() { Some(self.current_heat) } else { None },
+            heats: self.heats.clone(),
+            categories,
+        }
+    }
+
+    fn get_path() -> PathBuf {
+        config_path("competition.json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn athlete(id: u64, category: &str) -> Athlete {
+        Athlete { id, name: format!("Athlete {}", id), bib: id as u32, category: category.to_string() }
+    }
+
+    fn competition() -> Competition {
+        let mut competition = Competition::default();
+        competition.start(vec![Heat { round: 1, lanes: vec![] }]);
+        competition
+    }
+
+    fn ranks(leaderboard: &Leaderboard) -> Vec<(usize, u64)> {
+        leaderboard.categories[0].entries.iter().map(|entry| (entry.rank, entry.athlete.id)).collect()
+    }
+
+    #[test]
+    fn tied_results_share_a_rank() {
+        let mut competition = competition();
+        competition.record(athlete(0, "Open"), 20_000, false);
+        competition.record(athlete(1, "Open"), 10_000, false);
+        competition.record(athlete(2, "Open"), 10_000, false);
+
+        assert_eq!(ranks(&competition.leaderboard()), vec![(1, 1), (1, 2), (3, 0)]);
+    }
+
+    #[test]
+    fn dnf_ranks_after_finished_runs() {
+        let mut competition = competition();
+        competition.record(athlete(0, "Open"), 5_000, true);
+        competition.record(athlete(1, "Open"), 60_000, false);
+
+        assert_eq!(ranks(&competition.leaderboard()), vec![(1, 1), (2, 0)]);
+    }
+
+    #[test]
+    fn best_result_counts_per_athlete() {
+        let mut competition = competition();
+        competition.record(athlete(0, "Open"), 30_000, false);
+        competition.record(athlete(0, "Open"), 5_000, true);
+        competition.record(athlete(0, "Open"), 20_000, false);
+        competition.record(athlete(1, "Masters"), 25_000, false);
+
+        let leaderboard = competition.leaderboard();
+        assert_eq!(leaderboard.categories.len(), 2);
+        assert_eq!(leaderboard.categories[0].category, "Masters");
+
+        let open = &leaderboard.categories[1].entries;
+        assert_eq!(open.len(), 1);
+        assert_eq!((open[0].millis, open[0].dnf), (20_000, false));
+    }
+}
@@ -0,0 +1,150 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::{Serialize, Deserialize};
+
+const USAGE: &str = "Usage: ninja-timer-mk2 [OPTIONS]
+
+Options:
+    --config <PATH>         JSON config file (default: ~/.config/ninja-timer/config.json)
+    --bind <ADDR>           Address of the web server (default: 0.0.0.0:8080)
+    --static-dir <PATH>     Directory with the web client (default: ./client/dist)
+    --input <SOURCE>        Button input: cringed, raspi, serial or none (default: cringed)
+    --socket <PATH>         cringed event socket (default: /tmp/cringed/events.sock)
+    --config-dir <PATH>     Directory for settings, runs and backgrounds (default: ~/.config/ninja-timer)
+    --startup-delay <SECS>  Seconds to wait before starting (default: 5)
+    --headless              Run without the SDL display
+    --help                  Print this message";
+
+// Where the buttons are read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InputSource {
+    // The cringed daemon, over socket_path
+    Cringed,
+    // GPIO pins of the Raspberry Pi itself
+    Raspi,
+    // Buzzers on a serial port, configured in the settings
+    Serial,
+    None,
+}
+
+impl std::str::FromStr for InputSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cringed" => Ok(InputSource::Cringed),
+            "raspi" => Ok(InputSource::Raspi),
+            "serial" => Ok(InputSource::Serial),
+            "none" => Ok(InputSource::None),
+            _ => Err(format!("Unknown input {}, expected cringed, raspi, serial or none", s)),
+        }
+    }
+}
+
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// Per-installation options, read from the config file and overridden by command line arguments
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    pub bind_address: String,
+    pub static_dir: PathBuf,
+    pub input: InputSource,
+    pub socket_path: PathBuf,
+    pub config_dir: PathBuf,
+    pub startup_delay: u64,
+    pub headless: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: "0.0.0.0:8080".to_string(),
+            static_dir: PathBuf::from("./client/dist"),
+            input: InputSource::Cringed,
+            socket_path: PathBuf::from("/tmp/cringed/events.sock"),
+            config_dir: default_config_dir(),
+            startup_delay: 5,
+            headless: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Result<Self, String> {
+        let args: Vec<String> = env::args().skip(1).collect();
+
+        let config_path = match args.iter().position(|arg| arg == "--config") {
+            Some(i) => Some(PathBuf::from(args.get(i + 1).ok_or("--config requires a value")?)),
+            None => None,
+        };
+
+        let mut config = match config_path {
+            Some(path) => Self::load(&path)?,
+            None => {
+                let path = default_config_dir().join("config.json");
+                if path.is_file() { Self::load(&path)? } else { Self::default() }
+            },
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} requires a value\n\n{}", arg, USAGE));
+
+            match arg.as_str() {
+                "--config" => { value()?; },
+                "--bind" => config.bind_address = value()?,
+                "--static-dir" => config.static_dir = value()?.into(),
+                "--input" => config.input = value()?.parse()?,
+                "--socket" => config.socket_path = value()?.into(),
+                "--config-dir" => config.config_dir = value()?.into(),
+                "--startup-delay" => config.startup_delay = value()?.parse().map_err(|_| "--startup-delay must be a number of seconds")?,
+                "--headless" => config.headless = true,
+                "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                },
+                _ => return Err(format!("Unknown argument {}\n\n{}", arg, USAGE)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn load(path: &Path) -> Result<Self, String> {
+        let config_string = fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read config {}: {}", path.display(), e))?;
+
+        serde_json::from_str(&config_string)
+            .map_err(|e| format!("Couldn't parse config {}: {}", path.display(), e))
+    }
+
+    // Makes the config directory known to the stores, only the first call has an effect
+    pub fn apply(&self) {
+        let _ = CONFIG_DIR.set(self.config_dir.clone());
+    }
+}
+
+// Location of a file kept in the config directory, e.g. settings.json
+pub fn config_path(file_name: &str) -> PathBuf {
+    CONFIG_DIR.get_or_init(default_config_dir).join(file_name)
+}
+
+// Renames an unreadable file to <name>.broken so the next save doesn't overwrite it
+pub fn move_aside(path: &Path) -> std::io::Result<PathBuf> {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".broken");
+    let broken_path = path.with_file_name(file_name);
+    fs::rename(path, &broken_path)?;
+    Ok(broken_path)
+}
+
+fn default_config_dir() -> PathBuf {
+    let home = env::var("HOME").unwrap();
+    let home_path = Path::new(&home);
+    home_path.join(".config/ninja-timer")
+}
@@ -12,28 +12,34 @@ use sdl2::render::{Canvas, TextureQuery, Texture, TextureCreator};
 use sdl2::video::{Window, FullscreenType, WindowContext};
 use sdl2::rwops::RWops;
 use sdl2::mixer::{self, Music};
+use chrono::Utc;
 
-use crate::state::OutputEvent;
-use crate::timer::{Timer, TimerState};
+use crate::state::{InputEvent, OutputEvent, Request};
+use crate::timer::{Timer, TimerState, format_millis};
+use crate::competition::Leaderboard;
 use crate::assets::{self, get_background_path};
-use crate::settings::Settings;
-use crate::info::Info;
+use crate::settings::{LaneMode, Settings};
+use crate::info::{ButtonStatus, Info};
+use crate::health::HardwareHealth;
 
 pub struct Display {
     receiver: mpsc::Receiver<OutputEvent>,
+    // Bound keys are sent to the state manager
+    sender: mpsc::Sender<Request>,
     timers: Vec<Timer>,
     settings: Option<Settings>,
     info: Option<Info>,
+    leaderboard: Option<Leaderboard>,
+    health: Option<HardwareHealth>,
     should_reload_background: bool,
     is_visible: bool,
+    show_leaderboard: bool,
 }
 
 const TARGET_FRAME_DURATION: Duration = Duration::from_millis(1000 / 30);
 
 struct WindowData {
     canvas: Canvas<Window>,
-    last_millis: i128,
-    last_state: TimerState,
     assigned_position: Option<(i16, i16)>,
     is_fullscreen: bool,
     is_visible: bool,
@@ -41,20 +47,25 @@ struct WindowData {
 }
 
 impl Display {
-    pub fn new(receiver: mpsc::Receiver<OutputEvent>) -> Self {
+    pub fn new(receiver: mpsc::Receiver<OutputEvent>, sender: mpsc::Sender<Request>) -> Self {
         Self {
             receiver,
+            sender,
             timers: vec![],
             settings: None,
             info: None,
+            leaderboard: None,
+            health: None,
             should_reload_background: true,
             is_visible: true,
+            show_leaderboard: false,
         }
     }
 
     pub fn show_windows(&mut self) -> Result<(), String> {
         let sdl_context = sdl2::init()?;
-
+        // Event timestamps are milliseconds since SDL was initialized
+        let sdl_started = Instant::now();
 
         let ttf_context = ttf::init().map_err(|e| e.to_string())?;
         let _image_context = image::init(InitFlag::PNG)?;
@@ -66,6 +77,7 @@ impl Display {
         let beep1 = Music::from_static_bytes(assets::BEEP1)?;
         let beep2 = Music::from_static_bytes(assets::BEEP2)?;
         let mut buzzer = Clip::new(Music::from_static_bytes(assets::BUZZER)?);
+        let false_start = Music::from_static_bytes(assets::FALSE_START)?;
 
         let video_subsystem = sdl_context.video()?;
         let displays = video_subsystem.num_video_displays()?;
@@ -83,6 +95,8 @@ impl Display {
         sdl_context.mouse().show_cursor(false);
 
         let mut font = ResizeableFont::load_from_bytes(&ttf_context, assets::FONT, 64)?;
+        let mut name_font = ResizeableFont::load_from_bytes(&ttf_context, assets::FONT, 32)?;
+        let mut list_font = ResizeableFont::load_from_bytes(&ttf_context, assets::FONT, 32)?;
         let debug_font = ResizeableFont::load_from_bytes(&ttf_context, assets::FONT, 20)?;
 
         
@@ -104,8 +118,6 @@ impl Display {
 
             windows.push(WindowData{ 
                 canvas, 
-                last_millis: 0,
-                last_state: TimerState::Reset,
                 assigned_position: display_bounds.get(i).map(|rect| (rect.x() as i16, rect.y() as i16)),
                 max_size: (display_bounds[i].width(), display_bounds[i].height()),
                 is_fullscreen: false,
@@ -119,9 +131,10 @@ impl Display {
         let mut event_pump = sdl_context.event_pump()?;
 
         let mut frame_duration: Duration = TARGET_FRAME_DURATION;
-        let mut start_sound_played = false;
+        let mut start_sounds_played: Vec<bool> = vec![];
 
-        let mut backgrounds = vec![None, None];
+        let mut backgrounds: Vec<_> = windows.iter().map(|_| None).collect();
+        let mut lane_states: Vec<LaneState> = vec![];
 
         'running: loop {
             let frame_start = Instant::now();
@@ -134,14 +147,24 @@ impl Display {
                         keycode: Some(Keycode::Escape),
                         ..
                     } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        timestamp,
+                        repeat: false,
+                        ..
+                    } => self.press_key(keycode, sdl_started + Duration::from_millis(timestamp as u64)),
                     _ => {}
                 }
             }
 
             self.handle_messages()?;
 
+            let assigned_lanes = assign_lanes(self.timers.len(), windows.len());
+            lane_states.resize_with(self.timers.len(), LaneState::default);
+
             for (i, window) in windows.iter_mut().enumerate() {
-                let window_enabled = i < self.timers.len() && i < display_bounds.len();
+                let lanes = &assigned_lanes[i];
+                let window_enabled = !lanes.is_empty();
                 self.sync_fullscreen(window);
 
                 if self.is_visible && window_enabled {
@@ -160,10 +183,6 @@ impl Display {
                     } else {
                         None
                     };
-
-                    if i == self.timers.len() - 1 {
-                        self.should_reload_background = false;
-                    }
                 }
 
                 if !window_enabled {
@@ -174,9 +193,10 @@ impl Display {
                 let viewport = window.canvas.viewport();
                 let width = viewport.width();
                 let height = viewport.height();
+                let row_height = height / lanes.len() as u32;
 
-                let timer = self.timers[i];
-                font.set_size(height as u16 / 3);
+                font.set_size(row_height as u16 / 3);
+                name_font.set_size(row_height as u16 / 8);
 
                 window.canvas.set_draw_color(Color::RGB(0, 0, 0));
                 window.canvas.clear();
@@ -185,20 +205,66 @@ impl Display {
                     window.canvas.copy(bg, None, None)?;
                 }
 
-                let color = match timer.get_state() {
-                    TimerState::CountingDown => Color::RGB(255, 0, 0),
-                    TimerState::Stopped => Color::RGB(0, 255, 0),
-                    _ => Color::RGB(255, 255, 255),
-                };
-
-                render_text(
-                    &timer.format(),
-                    &Point::new(width as i32 / 2, height as i32 / 2),
-                    &font.inner,
-                    &mut window.canvas,
-                    &color,
-                    Align::Center,
-                )?;
+                if self.show_leaderboard {
+                    list_font.set_size(height as u16 / 20);
+                    render_leaderboard(self.leaderboard.as_ref(), &list_font.inner, &mut window.canvas, width, height)?;
+                } else {
+                    for (row, &lane) in lanes.iter().enumerate() {
+                        let timer = &self.timers[lane];
+                        let row_top = (row as u32 * row_height) as i32;
+
+                        let color = match timer.get_state() {
+                            TimerState::CountingDown => Color::RGB(255, 0, 0),
+                            TimerState::Stopped => Color::RGB(0, 255, 0),
+                            TimerState::FalseStart => Color::RGB(255, 140, 0),
+                            TimerState::Dnf => Color::RGB(255, 0, 255),
+                            _ => Color::RGB(255, 255, 255),
+                        };
+
+                        render_text(
+                            &timer.format(),
+                            &Point::new(width as i32 / 2, row_top + row_height as i32 / 2),
+                            &font.inner,
+                            &mut window.canvas,
+                            &color,
+                            Align::Center,
+                        )?;
+
+                        if let Some(ref athlete) = timer.athlete {
+                            render_text(
+                                &format!("#{} {}", athlete.bib, athlete.name),
+                                &Point::new(width as i32 / 2, row_top + row_height as i32 / 6),
+                                &name_font.inner,
+                                &mut window.canvas,
+                                &Color::RGB(255, 255, 255),
+                                Align::Center,
+                            )?;
+                        }
+
+                        let splits = timer.split_millis();
+                        if let Some(millis) = splits.last() {
+                            render_text(
+                                &format!("Split {}: {}", splits.len(), format_millis(*millis)),
+                                &Point::new(width as i32 / 2, row_top + row_height as i32 * 5 / 6),
+                                &name_font.inner,
+                                &mut window.canvas,
+                                &Color::RGB(255, 255, 255),
+                                Align::Center,
+                            )?;
+                        }
+
+                        if lanes.len() > 1 {
+                            render_text(
+                                &format!("Lane {}", lane + 1),
+                                &Point::new(10, row_top + 10),
+                                &debug_font.inner,
+                                &mut window.canvas,
+                                &Color::RGB(255, 255, 255),
+                                Align::TopLeft,
+                            )?;
+                        }
+                    }
+                }
 
                 // Debug
                 if self.debug_enabled() {
@@ -235,33 +301,88 @@ impl Display {
                         Align::TopLeft,
                     )?;
 
+                    if let Some(ref info) = self.info {
+                        let config = &info.config;
+                        let lines = [
+                            format!("Web: {} ({})", config.bind_address, config.static_dir.display()),
+                            format!("Socket: {}", config.socket_path.display()),
+                            format!("Config dir: {}", config.config_dir.display()),
+                            describe_buttons(&info.buttons),
+                        ];
+
+                        for (n, line) in lines.iter().enumerate() {
+                            render_text(
+                                line,
+                                &Point::new(10, 50 + 20 * n as i32),
+                                &debug_font.inner,
+                                &mut window.canvas,
+                                &Color::RGB(0, 0, 0),
+                                Align::TopLeft,
+                            )?;
+                        }
+                    }
+
+                }
+
+                // Hardware faults stay on screen until an operator clears them
+                if let Some(fault) = self.health.as_ref().and_then(|health| health.last_fault()) {
+                    let fault_count = self.health.as_ref().map_or(0, |health| health.fault_count);
+
+                    window.canvas.set_draw_color(Color::RGB(200, 0, 0));
+                    window.canvas.fill_rect(Rect::new(0, height as i32 - 40, width, 40))?;
+
+                    render_text(
+                        &format!("Hardware fault: {} ({} total)", fault.describe(), fault_count),
+                        &Point::new(10, height as i32 - 30),
+                        &debug_font.inner,
+                        &mut window.canvas,
+                        &Color::RGB(255, 255, 255),
+                        Align::TopLeft,
+                    )?;
                 }
 
                 window.canvas.present();
+            }
 
-                // Audio
+            self.should_reload_background = false;
+
+            // Audio, in a race the start beeps follow the first lane that wasn't disqualified,
+            // independent lanes each beep for themselves
+            let independent = matches!(self.settings, Some(ref settings) if settings.lane_mode == LaneMode::Independent);
+            let clock_lane = self.timers
+                .iter()
+                .position(|timer| timer.get_state() != TimerState::FalseStart)
+                .unwrap_or(0);
+            start_sounds_played.resize(self.timers.len(), false);
+
+            for (lane, timer) in self.timers.iter().enumerate() {
+                let lane_state = &mut lane_states[lane];
                 let millis = timer.as_millis();
+                let beeps = independent || lane == clock_lane;
+                let start_sound_played = &mut start_sounds_played[if independent { lane } else { 0 }];
 
                 let state = timer.get_state();
                 if state == TimerState::CountingDown {
-                    if i == 0 && millis / 1000 != window.last_millis / 1000 {
+                    if beeps && millis / 1000 != lane_state.last_millis / 1000 {
                         beep1.play(1)?;
                     }
-                } else if (window.last_millis < 0 && millis >= 0) || (state == TimerState::Running && !start_sound_played) {
-                    if i == 0 {
+                } else if (lane_state.last_millis < 0 && millis >= 0) || (state == TimerState::Running && !*start_sound_played) {
+                    if beeps {
                         beep2.play(1)?;
-                        start_sound_played = true;
+                        *start_sound_played = true;
                     }
-                } else if window.last_state == TimerState::Running && state == TimerState::Stopped {
+                } else if lane_state.last_state == TimerState::Running && (state == TimerState::Stopped || state == TimerState::Dnf) {
                     buzzer.play_duration(Duration::from_secs(2))?;
+                } else if lane_state.last_state != TimerState::FalseStart && state == TimerState::FalseStart {
+                    false_start.play(1)?;
                 }
 
-                if i == 0 && (state == TimerState::CountingDown || state == TimerState::Stopped || state == TimerState::Reset) {
-                    start_sound_played = false;
+                if beeps && (state == TimerState::CountingDown || state == TimerState::Stopped || state == TimerState::Reset) {
+                    *start_sound_played = false;
                 }
 
-                window.last_millis = millis;
-                window.last_state = state;
+                lane_state.last_millis = millis;
+                lane_state.last_state = state;
             }
 
             buzzer.update();
@@ -276,6 +397,15 @@ impl Display {
         Ok(())
     }
 
+    fn press_key(&self, keycode: Keycode, pressed_at: Instant) {
+        let key = keycode.name();
+        let is_bound = matches!(self.settings, Some(ref settings) if settings.key_action(&key).is_some());
+
+        if is_bound {
+            let _ = self.sender.send(InputEvent::PressKey(key, Some(pressed_at)).into());
+        }
+    }
+
     fn debug_enabled(&self) -> bool {
         if let None = self.settings {
             return false
@@ -293,7 +423,7 @@ impl Display {
 
         match self.settings {
             None => return,
-            Some(settings) => {
+            Some(ref settings) => {
                 if window_data.is_fullscreen == settings.fullscreen {
                     return;
                 }
@@ -326,10 +456,13 @@ impl Display {
                     OutputEvent::SyncTimers(timers) => {
                         self.timers = timers;
                     },
-                    OutputEvent::SyncSettings(settings) => self.settings = Some(settings),
-                    OutputEvent::SyncInfo(info) => self.info = Some(info),
+                    OutputEvent::SyncSettings(settings) => self.settings = Some(*settings),
+                    OutputEvent::SyncInfo(info) => self.info = Some(*info),
                     OutputEvent::ReloadBackground => self.should_reload_background = true,
                     OutputEvent::SetDisplay(x) => self.is_visible = x,
+                    OutputEvent::SyncLeaderboard(leaderboard) => self.leaderboard = Some(leaderboard),
+                    OutputEvent::SetLeaderboard(x) => self.show_leaderboard = x,
+                    OutputEvent::SyncHealth(health) => self.health = Some(health),
                     #[allow(unreachable_patterns)]
                     _ => (),
                 },
@@ -342,6 +475,34 @@ impl Display {
     }
 }
 
+#[derive(Default)]
+struct LaneState {
+    last_millis: i128,
+    last_state: TimerState,
+}
+
+// Spread lanes over windows in order, one lane per window while there are
+// enough displays and stacking the rest on the first windows otherwise.
+fn assign_lanes(lanes: usize, windows: usize) -> Vec<Vec<usize>> {
+    let mut assigned = vec![vec![]; windows];
+
+    if windows == 0 {
+        return assigned;
+    }
+
+    let per_window = lanes / windows;
+    let extra = lanes % windows;
+    let mut lane = 0;
+
+    for (i, window_lanes) in assigned.iter_mut().enumerate() {
+        let count = per_window + usize::from(i < extra);
+        window_lanes.extend(lane..lane + count);
+        lane += count;
+    }
+
+    assigned
+}
+
 struct ResizeableFont<'ttf_module, 'rwops> {
     ctx: &'ttf_module Sdl2TtfContext,
     bytes: &'static [u8],
@@ -397,9 +558,71 @@ impl<'a> Clip<'a> {
     }
 }
 
+// Connection of the button daemon and how long ago a button was last pressed, for the debug overlay
+fn describe_buttons(buttons: &ButtonStatus) -> String {
+    if !buttons.connected {
+        return "Buttons: OFFLINE".to_string();
+    }
+
+    match buttons.last_event_at.map(|at| Utc::now() - at) {
+        Some(age) => format!("Buttons: connected, last event {}s ago", age.num_seconds()),
+        None => "Buttons: connected, no events yet".to_string(),
+    }
+}
+
+// Lists the standings of every category top to bottom, cutting off whatever doesn't fit
+fn render_leaderboard(leaderboard: Option<&Leaderboard>, font: &Font, canvas: &mut Canvas<Window>, width: u32, height: u32) -> Result<(), String> {
+    let line_height = font.height() * 6 / 5;
+    let left = width as i32 / 10;
+    let right = width as i32 * 9 / 10;
+    let white = Color::RGB(255, 255, 255);
+    let mut y = line_height / 2;
+
+    let title = match leaderboard.and_then(|x| x.current_heat.map(|heat| (heat, x.heats.len()))) {
+        Some((heat, heats)) => format!("Leaderboard - heat {}/{}", heat + 1, heats),
+        None => "Leaderboard".to_string(),
+    };
+    render_text(&title, &Point::new(width as i32 / 2, y), font, canvas, &white, Align::Center)?;
+    y += line_height;
+
+    let categories = leaderboard.map_or(&[][..], |x| &x.categories[..]);
+
+    for standings in categories {
+        if y + line_height > height as i32 {
+            break;
+        }
+
+        y += line_height / 2;
+        let category = if standings.category.is_empty() { "Open" } else { &standings.category };
+        render_text(category, &Point::new(left, y), font, canvas, &Color::RGB(255, 200, 0), Align::TopLeft)?;
+        y += line_height;
+
+        for entry in &standings.entries {
+            if y + line_height > height as i32 {
+                break;
+            }
+
+            render_text(
+                &format!("{}. #{} {}", entry.rank, entry.athlete.bib, entry.athlete.name),
+                &Point::new(left, y),
+                font,
+                canvas,
+                &white,
+                Align::TopLeft,
+            )?;
+            let time = if entry.dnf { format!("DNF {}", format_millis(entry.millis)) } else { format_millis(entry.millis) };
+            render_text(&time, &Point::new(right, y), font, canvas, &white, Align::TopRight)?;
+            y += line_height;
+        }
+    }
+
+    Ok(())
+}
+
 enum Align {
     Center,
     TopLeft,
+    TopRight,
 }
 
 fn render_text(text: &str, point: &Point, font: &Font, canvas: &mut Canvas<Window>, color: &Color, align: Align) -> Result<(), String> {
@@ -419,6 +642,7 @@ fn render_text(text: &str, point: &Point, font: &Font, canvas: &mut Canvas<Windo
         Align::Center =>
             Rect::new(point.x() - (width as i32) / 2, point.y() - (height as i32) / 2, width, height),
         Align::TopLeft => Rect::new(point.x(), point.y(), width, height),
+        Align::TopRight => Rect::new(point.x() - width as i32, point.y(), width, height),
     };
 
     canvas.copy(&texture, None, rect)
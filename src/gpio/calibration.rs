@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Presses reported longer ago than this are taken as a restarted daemon rather than latency
+const MAX_LATENCY: Duration = Duration::from_secs(1);
+
+// Events older than this no longer calibrate, as the counter of the daemon drifts against ours
+const WINDOW: Duration = Duration::from_secs(60);
+const MAX_SAMPLES: usize = 64;
+
+// Maps the millisecond counter of the cringed daemon onto local Instants.
+// The event of the last minute that arrived fastest sets the offset between both clocks, every
+// other event is assumed to have been pressed that much before it arrived late.
+#[derive(Debug, Default)]
+pub struct DeviceClock {
+    // Arrival and timestamp of the recent events, oldest first
+    samples: VecDeque<(Instant, u32)>,
+}
+
+impl DeviceClock {
+    // Local time of a press with the given device timestamp that was received at `arrived_at`
+    pub fn pressed_at(&mut self, timestamp_ms: u32, arrived_at: Instant) -> Instant {
+        // Wrapping keeps working when the u32 counter overflows after ~49 days
+        let estimate = |&(sample_at, sample_ms): &(Instant, u32)| {
+            sample_at + Duration::from_millis(timestamp_ms.wrapping_sub(sample_ms) as u64)
+        };
+
+        // Samples that put the press too far back belong to a restarted daemon
+        self.samples.retain(|sample| {
+            arrived_at - sample.0 <= WINDOW && arrived_at.saturating_duration_since(estimate(sample)) <= MAX_LATENCY
+        });
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((arrived_at, timestamp_ms));
+
+        self.samples.iter().map(estimate).min().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_events_are_moved_back_to_the_press() {
+        let start = Instant::now();
+        let mut clock = DeviceClock::default();
+
+        assert_eq!(clock.pressed_at(1000, start), start);
+        // pressed 500ms later but delivered with 30ms latency
+        assert_eq!(clock.pressed_at(1500, start + Duration::from_millis(530)), start + Duration::from_millis(500));
+    }
+
+    #[test]
+    fn faster_events_recalibrate() {
+        let start = Instant::now();
+        let mut clock = DeviceClock::default();
+
+        clock.pressed_at(1000, start + Duration::from_millis(40));
+        // arrives earlier than the first offset allows, so the first one was late
+        assert_eq!(clock.pressed_at(2000, start + Duration::from_millis(1010)), start + Duration::from_millis(1010));
+        assert_eq!(clock.pressed_at(3000, start + Duration::from_millis(2020)), start + Duration::from_millis(2010));
+    }
+
+    #[test]
+    fn drifting_counter_stays_calibrated() {
+        let start = Instant::now();
+        let mut clock = DeviceClock::default();
+
+        // the counter of the daemon runs 100ppm slow, so it's 720ms behind after two hours
+        let press = |clock: &mut DeviceClock, since_start: Duration, latency_ms: u64| {
+            let timestamp_ms = 1000 + (since_start.as_millis() as u64 * 9999 / 10000) as u32;
+            clock.pressed_at(timestamp_ms, start + since_start + Duration::from_millis(latency_ms))
+        };
+
+        press(&mut clock, Duration::ZERO, 2);
+        for i in 1..1440 {
+            press(&mut clock, Duration::from_secs(i * 5), if i % 4 == 0 { 2 } else { 30 });
+        }
+
+        let pressed_at = press(&mut clock, Duration::from_secs(7200), 25);
+        let expected = start + Duration::from_secs(7200);
+        let error = pressed_at.max(expected) - pressed_at.min(expected);
+        assert!(error < Duration::from_millis(10), "off by {:?}", error);
+    }
+
+    #[test]
+    fn restarted_daemon_recalibrates() {
+        let start = Instant::now();
+        let mut clock = DeviceClock::default();
+
+        clock.pressed_at(50_000, start);
+        assert_eq!(clock.pressed_at(10, start + Duration::from_secs(5)), start + Duration::from_secs(5));
+    }
+}
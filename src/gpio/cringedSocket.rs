@@ -1,16 +1,21 @@
 
 use std::thread::{spawn, JoinHandle};
-use crate::state::InputEvent;
+use std::path::PathBuf;
+use crate::state::{InputEvent, Request};
+use crate::health::FaultKind;
 use std::sync::mpsc::Sender;
 
 use std::os::unix::net::UnixStream;
 
 use std::io::{BufReader, BufRead, ErrorKind};
+use std::time::{Duration, Instant};
 
+use super::calibration::DeviceClock;
+use super::filter::InputFilter;
+use super::{InputBackend, SharedInputSettings, StopSignal, RECONNECT_DELAY};
 
-const SOCKET_PATH: &'static str = "/tmp/cringed/events.sock";
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy)]
 pub(crate) enum EvtType {
     ButtonPress,
     ButtonRelease,
@@ -37,26 +42,56 @@ fn parse_event(event: &str) -> CringeEvt{
     return cevt;
 }
 
-pub (crate) const BUTTON_1: u8 = 1;
-pub (crate) const BUTTON_2: u8 = 2;
+pub struct CringedSocket {
+    socket_path: PathBuf,
+    settings: SharedInputSettings,
+    reconnect_delay: Duration,
+}
+
+impl CringedSocket {
+    pub fn new(socket_path: PathBuf, settings: SharedInputSettings) -> Self {
+        CringedSocket { socket_path, settings, reconnect_delay: RECONNECT_DELAY }
+    }
+
+    #[cfg(test)]
+    pub fn reconnect_every(self, reconnect_delay: Duration) -> Self {
+        CringedSocket { reconnect_delay, ..self }
+    }
+}
 
-const BUTTON_DEBUG: u8 = 9;
+impl InputBackend for CringedSocket {
+    fn name(&self) -> &'static str {
+        "cringed"
+    }
 
-pub fn spawn_gpio(sender: Sender<InputEvent>) -> JoinHandle<()> {
+    fn spawn(self: Box<Self>, sender: Sender<Request>, stop: StopSignal) -> JoinHandle<()> {
+        spawn_gpio(sender, *self, stop)
+    }
+}
+
+fn spawn_gpio(sender: Sender<Request>, backend: CringedSocket, stop: StopSignal) -> JoinHandle<()> {
     spawn(move || {
-        loop {
+        while !stop.wait(backend.reconnect_delay) {
             // println!("loop");
-            std::thread::sleep(std::time::Duration::from_secs(1));
             // Connect to socket
-            let stream = match UnixStream::connect(SOCKET_PATH) {
+            let stream = match UnixStream::connect(&backend.socket_path) {
                 Err(_) => continue,
                 Ok(stream) => stream,
             };
             stream.set_read_timeout(Some(std::time::Duration::from_millis(100))).unwrap();
     
+            sender.send(InputEvent::SetButtonsConnected(true).into()).unwrap();
+
             let mut reader = BufReader::new(&stream);
+            // The daemon may have restarted, so its counter is calibrated again on every connection
+            let mut device_clock = DeviceClock::default();
+            let mut filter = InputFilter::new(backend.settings.clone());
     
-            loop {
+            while !stop.is_stopped() {
+                for event in filter.poll(Instant::now()) {
+                    sender.send(event.into()).unwrap();
+                }
+
                 let mut my_str = String::new();
                 match reader.read_line(&mut my_str) {
                     Ok(0) => {
@@ -68,21 +103,27 @@ pub fn spawn_gpio(sender: Sender<InputEvent>) -> JoinHandle<()> {
                         match my_str.len() {
                             0 => std::thread::sleep(std::time::Duration::from_millis(50)),
                             _ => {
+                                let arrived_at = Instant::now();
                                 let evt = parse_event(&my_str);
                                 match evt.event_type {
-                                    EvtType::ButtonPress => {
-                                        match evt.io_bank_num {
-                                            BUTTON_1 | BUTTON_2 | BUTTON_DEBUG => sender.send(InputEvent::SetButtonState(evt.io_bank_num, true)).unwrap(),
-                                            x => println!("Unrecognized io_bank_num={x}"),
+                                    // Lane buttons are mapped in StateManager, so every bank is forwarded
+                                    EvtType::ButtonPress | EvtType::ButtonRelease => {
+                                        let pressed = matches!(evt.event_type, EvtType::ButtonPress);
+                                        let at = device_clock.pressed_at(evt.timestamp_ms, arrived_at);
+
+                                        for event in filter.edge(evt.io_bank_num, pressed, at) {
+                                            sender.send(event.into()).unwrap();
                                         }
                                     },
-                                    EvtType::ButtonRelease => {
-                                        match evt.io_bank_num {
-                                            BUTTON_1 | BUTTON_2 | BUTTON_DEBUG => sender.send(InputEvent::SetButtonState(evt.io_bank_num, false)).unwrap(),
-                                            x => println!("Unrecognized io_bank_num={x}"),
-                                        }
+                                    EvtType::Overcurrent => {
+                                        sender.send(InputEvent::ReportFault(FaultKind::Overcurrent, evt.io_bank_num).into()).unwrap();
+                                    }
+                                    EvtType::CriticalError => {
+                                        sender.send(InputEvent::ReportFault(FaultKind::CriticalError, evt.io_bank_num).into()).unwrap();
+                                    }
+                                    EvtType::TransportError => {
+                                        sender.send(InputEvent::ReportFault(FaultKind::TransportError, evt.io_bank_num).into()).unwrap();
                                     }
-                                _ => {}
                                 }
                             },
                         }
@@ -91,11 +132,13 @@ pub fn spawn_gpio(sender: Sender<InputEvent>) -> JoinHandle<()> {
                         continue; // no data, but socket alive
                     }
                     Err(e) => {
-                        // warn!("Error reading from socket {}",e);
+                        eprintln!("Error reading from socket {}", e);
                         break;
                     }
                 }
             }
+
+            sender.send(InputEvent::SetButtonsConnected(false).into()).unwrap();
         }
     })
 }
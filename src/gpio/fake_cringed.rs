@@ -0,0 +1,97 @@
+use std::fs;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{spawn, sleep, JoinHandle};
+use std::time::Duration;
+
+use super::cringedSocket::{CringeEvt, EvtType};
+
+static NEXT_SOCKET: AtomicUsize = AtomicUsize::new(0);
+
+// One line the fake daemon sends, at `timestamp_ms` on its own clock
+pub struct ScriptedEvent {
+    pub io_bank_num: u8,
+    pub event_type: EvtType,
+    pub timestamp_ms: u32,
+    // Extra delay before the event is delivered, like a busy daemon
+    pub latency: Duration,
+}
+
+impl ScriptedEvent {
+    pub fn new(timestamp_ms: u32, io_bank_num: u8, event_type: EvtType) -> Self {
+        ScriptedEvent { io_bank_num, event_type, timestamp_ms, latency: Duration::ZERO }
+    }
+
+    pub fn press(timestamp_ms: u32, io_bank_num: u8) -> Self {
+        Self::new(timestamp_ms, io_bank_num, EvtType::ButtonPress)
+    }
+
+    pub fn release(timestamp_ms: u32, io_bank_num: u8) -> Self {
+        Self::new(timestamp_ms, io_bank_num, EvtType::ButtonRelease)
+    }
+
+    pub fn late_by(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+}
+
+// Stands in for the cringed daemon: accepts one connection on a fresh socket, replays the
+// script in real time as newline-delimited CringeEvt JSON, then hangs up and removes the socket
+pub struct FakeCringed {
+    pub socket_path: PathBuf,
+    handle: JoinHandle<()>,
+}
+
+impl FakeCringed {
+    pub fn serve(script: Vec<ScriptedEvent>) -> Self {
+        let socket_path = std::env::temp_dir().join(format!(
+            "ninja-timer-fake-cringed-{}-{}.sock",
+            std::process::id(),
+            NEXT_SOCKET.fetch_add(1, Ordering::Relaxed),
+        ));
+        let _ = fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let path = socket_path.clone();
+        let handle = spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            replay(&mut stream, &script);
+
+            drop(stream);
+            let _ = fs::remove_file(&path);
+        });
+
+        FakeCringed { socket_path, handle }
+    }
+
+    // Waits until the whole script was sent
+    pub fn join(self) {
+        self.handle.join().unwrap();
+    }
+}
+
+fn replay(stream: &mut impl Write, script: &[ScriptedEvent]) {
+    let mut previous_ms = script.first().map_or(0, |event| event.timestamp_ms);
+    let mut pending_latency = Duration::ZERO;
+
+    for event in script {
+        let since_previous = Duration::from_millis(event.timestamp_ms.wrapping_sub(previous_ms) as u64);
+        // Latency of the previous event already used up part of the gap
+        sleep(since_previous.saturating_sub(pending_latency) + event.latency);
+        previous_ms = event.timestamp_ms;
+        pending_latency = event.latency;
+
+        let line = serde_json::to_string(&CringeEvt {
+            io_bank_num: event.io_bank_num,
+            event_type: event.event_type,
+            timestamp_ms: event.timestamp_ms,
+        }).unwrap();
+
+        if writeln!(stream, "{}", line).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::settings::ButtonFilter;
+use crate::state::InputEvent;
+
+use super::SharedInputSettings;
+
+#[derive(Debug, Default)]
+struct ButtonState {
+    // Last level reported by the backend and when it changed to it
+    raw: bool,
+    raw_at: Option<Instant>,
+    // Level passed on to the StateManager
+    settled: bool,
+    // First edge away from the settled level, kept through bounces until a level held
+    changing_since: Option<Instant>,
+    // Press dropped by ignore_after_stop_ms, so its release is dropped too
+    ignored: bool,
+    ignore_until: Option<Instant>,
+}
+
+// Applies the button filters of the settings to the edges of a backend, before they reach the StateManager
+#[derive(Debug, Default)]
+pub struct InputFilter {
+    settings: SharedInputSettings,
+    buttons: HashMap<u8, ButtonState>,
+}
+
+impl InputFilter {
+    pub fn new(settings: SharedInputSettings) -> Self {
+        InputFilter { settings, buttons: HashMap::new() }
+    }
+
+    // Events to send for a button pressed or released at the given time
+    pub fn edge(&mut self, io_bank_num: u8, pressed: bool, at: Instant) -> Vec<InputEvent> {
+        let filter = match self.filter(io_bank_num) {
+            Some(filter) => filter,
+            None => return vec![InputEvent::SetButtonState(io_bank_num, pressed, Some(at))],
+        };
+        let button = self.buttons.entry(io_bank_num).or_default();
+
+        // The previous level may have held long enough before this edge, even if nobody polled
+        let mut events: Vec<_> = settle(io_bank_num, button, &filter, at).into_iter().collect();
+
+        if pressed != button.raw {
+            button.raw = pressed;
+            button.raw_at = Some(at);
+            button.changing_since.get_or_insert(at);
+        }
+
+        events.extend(settle(io_bank_num, button, &filter, at));
+        events
+    }
+
+    // Levels that have held long enough by now
+    pub fn poll(&mut self, now: Instant) -> Vec<InputEvent> {
+        let mut events = vec![];
+
+        let settings = self.settings.read().unwrap();
+
+        for (&io_bank_num, button) in self.buttons.iter_mut() {
+            let filter = settings.button_filters.iter().find(|filter| filter.io_bank_num == io_bank_num);
+            if let Some(filter) = filter {
+                events.extend(settle(io_bank_num, button, filter, now));
+            }
+        }
+
+        events
+    }
+
+    fn filter(&self, io_bank_num: u8) -> Option<ButtonFilter> {
+        self.settings
+            .read()
+            .unwrap()
+            .button_filters
+            .iter()
+            .find(|filter| filter.io_bank_num == io_bank_num)
+            .cloned()
+    }
+}
+
+// Passes on the raw level once it held for the debounce, and a press once it was held for the minimum
+fn settle(io_bank_num: u8, button: &mut ButtonState, filter: &ButtonFilter, now: Instant) -> Option<InputEvent> {
+    let (changing_since, raw_at) = match (button.changing_since, button.raw_at) {
+        (Some(changing_since), Some(raw_at)) => (changing_since, raw_at),
+        _ => return None,
+    };
+
+    if now - raw_at < Duration::from_millis(filter.debounce_ms) {
+        return None;
+    }
+
+    if button.raw == button.settled {
+        // Bounced back and stayed there
+        button.changing_since = None;
+        return None;
+    }
+
+    if button.raw && now - changing_since < Duration::from_millis(filter.min_hold_ms) {
+        return None;
+    }
+
+    button.settled = button.raw;
+    button.changing_since = None;
+
+    if button.raw {
+        button.ignored = button.ignore_until.is_some_and(|until| changing_since < until);
+        if button.ignored {
+            return None;
+        }
+
+        button.ignore_until = Some(changing_since + Duration::from_millis(filter.ignore_after_stop_ms));
+    } else if button.ignored {
+        button.ignored = false;
+        return None;
+    }
+
+    // Timed at the first edge, bouncing only delays it
+    Some(InputEvent::SetButtonState(io_bank_num, button.raw, Some(changing_since)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, RwLock};
+
+    use super::super::InputSettings;
+
+    fn filter(debounce_ms: u64, min_hold_ms: u64, ignore_after_stop_ms: u64) -> InputFilter {
+        let settings = InputSettings {
+            button_filters: vec![ButtonFilter { io_bank_num: 1, debounce_ms, min_hold_ms, ignore_after_stop_ms }],
+            ..InputSettings::default()
+        };
+
+        InputFilter::new(Arc::new(RwLock::new(settings)))
+    }
+
+    fn edges(events: Vec<InputEvent>) -> Vec<(bool, Instant)> {
+        events.into_iter()
+            .map(|event| match event {
+                InputEvent::SetButtonState(1, pressed, Some(at)) => (pressed, at),
+                event => panic!("Unexpected {:?}", event),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unfiltered_buttons_pass() {
+        let start = Instant::now();
+        let mut filter = filter(50, 0, 0);
+
+        assert_eq!(filter.edge(2, true, start).len(), 1);
+        assert_eq!(filter.edge(2, false, start).len(), 1);
+    }
+
+    #[test]
+    fn bounces_are_dropped() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut filter = filter(50, 0, 0);
+
+        assert!(filter.edge(1, true, ms(0)).is_empty());
+        assert!(filter.edge(1, false, ms(10)).is_empty());
+        assert!(filter.edge(1, true, ms(20)).is_empty());
+        assert!(filter.poll(ms(60)).is_empty());
+        assert_eq!(edges(filter.poll(ms(70))), vec![(true, ms(0))]);
+        assert!(filter.edge(1, false, ms(200)).is_empty());
+        assert_eq!(edges(filter.poll(ms(250))), vec![(false, ms(200))]);
+    }
+
+    #[test]
+    fn taps_shorter_than_the_debounce_are_released() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut filter = filter(50, 0, 0);
+
+        // the release held, so the next press isn't taken for a bounce
+        assert!(filter.edge(1, true, ms(0)).is_empty());
+        assert!(filter.edge(1, false, ms(30)).is_empty());
+        assert!(filter.edge(1, true, ms(1000)).is_empty());
+        assert_eq!(edges(filter.poll(ms(1050))), vec![(true, ms(1000))]);
+
+        // a press that never held isn't passed on at all
+        assert!(filter.edge(1, false, ms(2000)).is_empty());
+        assert_eq!(edges(filter.poll(ms(2050))), vec![(false, ms(2000))]);
+        assert!(filter.edge(1, true, ms(3000)).is_empty());
+        assert!(filter.edge(1, false, ms(3030)).is_empty());
+        assert!(filter.poll(ms(3200)).is_empty());
+    }
+
+    #[test]
+    fn short_presses_are_dropped() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut filter = filter(0, 100, 0);
+
+        assert!(filter.edge(1, true, ms(0)).is_empty());
+        assert!(filter.edge(1, false, ms(30)).is_empty());
+        assert!(filter.poll(ms(200)).is_empty());
+
+        // a long one keeps the time it was pressed
+        assert!(filter.edge(1, true, ms(500)).is_empty());
+        assert!(filter.poll(ms(550)).is_empty());
+        assert_eq!(edges(filter.poll(ms(610))), vec![(true, ms(500))]);
+        assert_eq!(edges(filter.edge(1, false, ms(700))), vec![(false, ms(700))]);
+    }
+
+    #[test]
+    fn presses_after_a_stop_are_ignored() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut filter = filter(0, 0, 1000);
+
+        assert_eq!(filter.edge(1, true, ms(0)).len(), 1);
+        assert_eq!(filter.edge(1, false, ms(100)).len(), 1);
+        assert!(filter.edge(1, true, ms(300)).is_empty());
+        assert!(filter.edge(1, false, ms(400)).is_empty());
+        assert_eq!(edges(filter.edge(1, true, ms(1200))), vec![(true, ms(1200))]);
+    }
+
+    #[test]
+    fn changed_filters_apply_right_away() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut filter = filter(0, 0, 0);
+
+        assert_eq!(filter.edge(1, true, ms(0)).len(), 1);
+        filter.settings.write().unwrap().button_filters[0].min_hold_ms = 100;
+        assert_eq!(filter.edge(1, false, ms(50)).len(), 1);
+        assert!(filter.edge(1, true, ms(100)).is_empty());
+        assert!(filter.edge(1, false, ms(150)).is_empty());
+    }
+}
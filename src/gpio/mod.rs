@@ -1,11 +1,264 @@
-
 mod cringedSocket;
+mod calibration;
+mod filter;
+mod serial;
+#[cfg(raspi)]
+mod raspi;
+#[cfg(test)]
+mod fake_cringed;
 
 use std::sync::mpsc::Sender;
-use crate::state::InputEvent;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Duration;
+use crate::config::{Config, InputSource};
+use crate::settings::{ButtonFilter, SerialSettings, Settings};
+use crate::state::Request;
 use std::thread::JoinHandle;
 
+// How long backends wait before connecting to their buttons again
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+// A source of button input, reading on its own thread and sending events to the state manager
+pub trait InputBackend: Send {
+    fn name(&self) -> &'static str;
+    // The thread ends soon after `stop` was triggered
+    fn spawn(self: Box<Self>, sender: Sender<Request>, stop: StopSignal) -> JoinHandle<()>;
+}
+
+// Tells a backend thread to finish, checked between reads and while waiting to reconnect
+#[derive(Debug, Clone, Default)]
+pub struct StopSignal(Arc<(Mutex<bool>, Condvar)>);
+
+impl StopSignal {
+    pub fn stop(&self) {
+        let (stopped, condvar) = &*self.0;
+        *stopped.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.0.0.lock().unwrap()
+    }
+
+    // Sleeps for the given time, returns early with true once the backend should stop
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (stopped, condvar) = &*self.0;
+        let (stopped, _) = condvar.wait_timeout_while(stopped.lock().unwrap(), timeout, |stopped| !*stopped).unwrap();
+        *stopped
+    }
+}
+
+// The part of the settings the input threads read while running
+#[derive(Debug, Clone, Default)]
+pub struct InputSettings {
+    pub serial: SerialSettings,
+    pub button_filters: Vec<ButtonFilter>,
+}
+
+impl InputSettings {
+    pub fn from_settings(settings: &Settings) -> Self {
+        InputSettings {
+            serial: settings.serial.clone(),
+            button_filters: settings.button_filters.clone(),
+        }
+    }
+}
+
+// Written by the StateManager whenever the settings change
+pub type SharedInputSettings = Arc<RwLock<InputSettings>>;
+
+// A running backend
+pub struct InputHandle {
+    stop: StopSignal,
+    thread: JoinHandle<()>,
+}
+
+impl InputHandle {
+    // Waits for the backend thread to finish
+    pub fn stop(self) {
+        self.stop.stop();
+        if self.thread.join().is_err() {
+            eprintln!("Input thread panicked");
+        }
+    }
+}
+
+// Backend picked by the config, None when the buttons are disabled
+pub fn input_backend(config: &Config, settings: &SharedInputSettings) -> Result<Option<Box<dyn InputBackend>>, String> {
+    let backend: Box<dyn InputBackend> = match config.input {
+        InputSource::Cringed => Box::new(cringedSocket::CringedSocket::new(config.socket_path.clone(), settings.clone())),
+        #[cfg(raspi)]
+        InputSource::Raspi => Box::new(raspi::RaspiGpio::new(settings.clone())),
+        #[cfg(not(raspi))]
+        InputSource::Raspi => return Err("The raspi input needs a build for the Raspberry Pi".to_string()),
+        InputSource::Serial => Box::new(serial::SerialInput::new(settings.clone())),
+        InputSource::None => return Ok(None),
+    };
+
+    Ok(Some(backend))
+}
+
+pub fn spawn_input(sender: Sender<Request>, config: &Config, settings: &SharedInputSettings) -> Result<Option<InputHandle>, String> {
+    Ok(input_backend(config, settings)?.map(|backend| spawn_backend(backend, sender)))
+}
+
+fn spawn_backend(backend: Box<dyn InputBackend>, sender: Sender<Request>) -> InputHandle {
+    println!("Reading buttons from {}", backend.name());
+    let stop = StopSignal::default();
+    let thread = backend.spawn(sender, stop.clone());
+
+    InputHandle { stop, thread }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::fake_cringed::{FakeCringed, ScriptedEvent};
+    use super::cringedSocket::{CringedSocket, EvtType};
+
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use crate::athletes::Roster;
+    use crate::clock::SystemClock;
+    use crate::competition::Competition;
+    use crate::health::FaultKind;
+    use crate::info::{ButtonStatus, Info};
+    use crate::runs::RunHistory;
+    use crate::state::{OutputEvent, StateManager};
+    use crate::timer::TimerState;
+
+    // Runs the cringed backend against the fake daemon and feeds a StateManager like main does,
+    // until the daemon hung up. Returns every output event.
+    fn run_script(settings: Settings, script: Vec<ScriptedEvent>) -> Vec<OutputEvent> {
+        let cringed = FakeCringed::serve(script);
+        let config = Config { socket_path: cringed.socket_path.clone(), ..Config::default() };
+
+        let (input_tx, input_rx) = mpsc::channel();
+        let (output_tx, output_rx) = mpsc::channel();
+        let input_settings = SharedInputSettings::default();
+        let backend = CringedSocket::new(cringed.socket_path.clone(), input_settings.clone()).reconnect_every(Duration::from_millis(10));
+
+        let info = Info { ips: vec![], number_displays: 1, config: config.clone(), buttons: ButtonStatus::default() };
+        let mut state_manager = StateManager::with_clock(
+            settings,
+            info,
+            Arc::new(Mutex::new(RunHistory::default())),
+            Arc::new(Mutex::new(Roster::default())),
+            Competition::default(),
+            Arc::new(SystemClock),
+        );
+        state_manager.add_listener(output_tx);
+        state_manager.share_input_settings(input_settings);
+        let input = spawn_backend(Box::new(backend), input_tx);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut outputs = vec![];
+        while Instant::now() < deadline {
+            if let Ok(request) = input_rx.recv_timeout(Duration::from_millis(10)) {
+                state_manager.handle(request);
+            }
+
+            for event in output_rx.try_iter() {
+                let disconnected = matches!(event, OutputEvent::SyncInfo(ref info) if info.buttons.changed_at.is_some() && !info.buttons.connected);
+                outputs.push(event);
+
+                if disconnected {
+                    input.stop();
+                    cringed.join();
+                    return outputs;
+                }
+            }
+        }
+
+        panic!("The fake cringed daemon never hung up");
+    }
+
+    fn last_timers(outputs: &[OutputEvent]) -> Vec<crate::timer::Timer> {
+        outputs.iter()
+            .rev()
+            .find_map(|event| match event {
+                OutputEvent::SyncTimers(timers) => Some(timers.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn presses_start_and_stop_the_race() {
+        let settings = Settings { countdown: 0, start_buttons: vec![5], ..Settings::default() };
+        let outputs = run_script(settings, vec![
+            ScriptedEvent::press(10_000, 5),
+            ScriptedEvent::release(10_050, 5),
+            // delivered late, but timed by the daemon's clock
+            ScriptedEvent::press(10_400, 1).late_by(Duration::from_millis(150)),
+            ScriptedEvent::release(10_450, 1),
+        ]);
+
+        let timers = last_timers(&outputs);
+        assert_eq!(timers[0].get_state(), TimerState::Stopped);
+        assert_eq!(timers[1].get_state(), TimerState::Running);
+
+        let millis = timers[0].as_millis();
+        assert!((380..=420).contains(&millis), "lane 1 stopped after {}ms", millis);
+    }
+
+    #[test]
+    fn filters_of_the_settings_apply() {
+        let settings = Settings {
+            countdown: 0,
+            start_buttons: vec![5],
+            button_filters: vec![ButtonFilter { io_bank_num: 1, min_hold_ms: 100, ..ButtonFilter::default() }],
+            ..Settings::default()
+        };
+        let outputs = run_script(settings, vec![
+            ScriptedEvent::press(10_000, 5),
+            ScriptedEvent::release(10_050, 5),
+            // too short to count
+            ScriptedEvent::press(10_300, 1),
+            ScriptedEvent::release(10_330, 1),
+            ScriptedEvent::press(10_600, 1),
+            ScriptedEvent::release(10_800, 1),
+        ]);
+
+        let millis = last_timers(&outputs)[0].as_millis();
+        assert!((580..=620).contains(&millis), "lane 1 stopped after {}ms", millis);
+    }
+
+    #[test]
+    fn faults_and_connection_are_reported() {
+        let outputs = run_script(Settings::default(), vec![
+            ScriptedEvent::new(500, 2, EvtType::Overcurrent),
+        ]);
+
+        let health = outputs.iter()
+            .rev()
+            .find_map(|event| match event {
+                OutputEvent::SyncHealth(health) => Some(health),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(health.faults[0].kind, FaultKind::Overcurrent);
+        assert_eq!(health.faults[0].io_bank_num, 2);
+
+        let connected: Vec<bool> = outputs.iter()
+            .filter_map(|event| match event {
+                OutputEvent::SyncInfo(info) => Some(info.buttons.connected),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(connected.first(), Some(&true));
+        assert_eq!(connected.last(), Some(&false));
+    }
+
+    #[test]
+    fn backends_follow_the_config() {
+        let config = |input| Config { input, ..Config::default() };
+        let settings = SharedInputSettings::default();
 
-pub fn spawn_gpio(sender: Sender<InputEvent>) -> JoinHandle<()> {
-    return cringedSocket::spawn_gpio(sender);
+        assert_eq!(input_backend(&config(InputSource::Cringed), &settings).unwrap().unwrap().name(), "cringed");
+        assert_eq!(input_backend(&config(InputSource::Serial), &settings).unwrap().unwrap().name(), "serial");
+        assert!(input_backend(&config(InputSource::None), &settings).unwrap().is_none());
+        assert_eq!(input_backend(&config(InputSource::Raspi), &settings).is_ok(), cfg!(raspi));
+    }
 }
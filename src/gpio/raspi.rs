@@ -5,13 +5,19 @@ use std::collections::HashMap;
 
 use rppal::gpio::{Gpio, Trigger, Level};
 
-use crate::state::InputEvent;
+use crate::state::{InputEvent, Request};
+
+use super::filter::InputFilter;
+use super::{InputBackend, SharedInputSettings, StopSignal};
 
 const BUZZER_PIN: u8 = 17;
 const DEBUG_PIN: u8 = 27;
 const DISPLAY_PIN: u8 = 22;
 const POWER_PIN: u8 = 23;
 
+// The buzzer is reported as the button of the first lane in the default settings
+const BUZZER_BUTTON: u8 = 1;
+
 const DEBOUNCE_DURATION: Duration = Duration::from_millis(50);
 
 struct Button {
@@ -37,7 +43,7 @@ impl Button {
     }
 
     fn get_changed_level(&mut self) -> Option<Level> {
-        if self.real_level == None || self.real_level == self.last_returned {
+        if self.real_level.is_none() || self.real_level == self.last_returned {
             return None;
         }
 
@@ -60,20 +66,43 @@ impl Button {
     }
 }
 
+// Buttons wired straight to the GPIO pins of the Raspberry Pi
+pub struct RaspiGpio {
+    settings: SharedInputSettings,
+}
+
+impl RaspiGpio {
+    pub fn new(settings: SharedInputSettings) -> Self {
+        RaspiGpio { settings }
+    }
+}
+
+impl InputBackend for RaspiGpio {
+    fn name(&self) -> &'static str {
+        "raspi"
+    }
+
+    fn spawn(self: Box<Self>, sender: Sender<Request>, stop: StopSignal) -> JoinHandle<()> {
+        spawn_gpio(self.settings, sender, stop)
+    }
+}
 
-fn spawn_gpio(sender: Sender<InputEvent>) -> JoinHandle<()> {
+fn spawn_gpio(settings: SharedInputSettings, sender: Sender<Request>, stop: StopSignal) -> JoinHandle<()> {
     spawn(move || {
         let gpio = Gpio::new().unwrap();
 
-        let power_pin = gpio.get(POWER_PIN).unwrap().into_output_high();
+        let _power_pin = gpio.get(POWER_PIN).unwrap().into_output_high();
+        // The pins are always there, so the buttons count as connected from now on
+        sender.send(InputEvent::SetButtonsConnected(true).into()).unwrap();
 
+        // The buzzer goes through the button filters of the settings like every other backend
+        let mut filter = InputFilter::new(settings);
         let mut buttons = HashMap::from([
-            (BUZZER_PIN, Button::new(None)),
             (DEBUG_PIN, Button::new(Some(DEBOUNCE_DURATION))),
             (DISPLAY_PIN, Button::new(Some(DEBOUNCE_DURATION))),
         ]);
 
-        let input_pins: Vec<_> = buttons.keys().into_iter().map(|pin_number| {
+        let input_pins: Vec<_> = buttons.keys().chain([&BUZZER_PIN]).map(|pin_number| {
             let mut pin = gpio.get(*pin_number).unwrap().into_input_pulldown();
             pin.set_interrupt(Trigger::Both).unwrap();
             pin
@@ -82,33 +111,34 @@ fn spawn_gpio(sender: Sender<InputEvent>) -> JoinHandle<()> {
         // Get Vec<&InputPin> from Vec<InputPin>
         let poll_pins: Vec<_> = input_pins.iter().collect();
 
-        loop {
+        while !stop.is_stopped() {
             let poll = gpio.poll_interrupts(&poll_pins, false, Some(Duration::from_millis(10)));
 
             if let Ok(Some((pin, level))) = poll {
-                if let Some(button) = buttons.get_mut(&pin.pin()) {
+                if pin.pin() == BUZZER_PIN {
+                    for event in filter.edge(BUZZER_BUTTON, level == Level::Low, Instant::now()) {
+                        sender.send(event.into()).unwrap();
+                    }
+                } else if let Some(button) = buttons.get_mut(&pin.pin()) {
                     button.update(level);
                 }
             }
 
+            for event in filter.poll(Instant::now()) {
+                sender.send(event.into()).unwrap();
+            }
+
             for (pin, button) in buttons.iter_mut() {
                 if let Some(level) = button.get_changed_level() {
-                    match *pin {
-                        BUZZER_PIN => {
-                            sender.send(InputEvent::SetButtonState(level == Level::Low)).unwrap();
-                        },
-                        DEBUG_PIN => {
-                            if level == Level::High {
-                                sender.send(InputEvent::ToggleDebug).unwrap();
-                            }
-                        },
-                        DISPLAY_PIN => {
-                            if level == Level::High {
-                                sender.send(InputEvent::ToggleDisplay).unwrap();
-                            }
-                        },
-                        _ => (),
+                    let event = match *pin {
+                        DEBUG_PIN if level == Level::High => Some(InputEvent::ToggleDebug),
+                        DISPLAY_PIN if level == Level::High => Some(InputEvent::ToggleDisplay),
+                        _ => None,
                     };
+
+                    if let Some(event) = event {
+                        sender.send(event.into()).unwrap();
+                    }
                 }
             }
         }
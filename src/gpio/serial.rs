@@ -0,0 +1,195 @@
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::sync::mpsc::Sender;
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::settings::SerialFormat;
+use crate::state::{InputEvent, Request};
+
+use super::filter::InputFilter;
+use super::{InputBackend, SharedInputSettings, StopSignal, RECONNECT_DELAY};
+
+// Buzzers behind a USB-serial adapter that sends a line per press
+pub struct SerialInput {
+    settings: SharedInputSettings,
+    reconnect_delay: Duration,
+}
+
+impl SerialInput {
+    pub fn new(settings: SharedInputSettings) -> Self {
+        SerialInput { settings, reconnect_delay: RECONNECT_DELAY }
+    }
+}
+
+impl InputBackend for SerialInput {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn spawn(self: Box<Self>, sender: Sender<Request>, stop: StopSignal) -> JoinHandle<()> {
+        spawn_serial(sender, *self, stop)
+    }
+}
+
+fn spawn_serial(sender: Sender<Request>, backend: SerialInput, stop: StopSignal) -> JoinHandle<()> {
+    spawn(move || {
+        while !stop.wait(backend.reconnect_delay) {
+            let serial = backend.settings.read().unwrap().serial.clone();
+
+            let port = match serialport::new(&serial.port, serial.baud).timeout(Duration::from_millis(100)).open() {
+                Err(_) => continue,
+                Ok(port) => port,
+            };
+
+            sender.send(InputEvent::SetButtonsConnected(true).into()).unwrap();
+            // A changed port, baud rate or format opens the port again
+            let done = || stop.is_stopped() || backend.settings.read().unwrap().serial != serial;
+            read_lines(port, &serial.formats, &mut InputFilter::new(backend.settings.clone()), &sender, done);
+            sender.send(InputEvent::SetButtonsConnected(false).into()).unwrap();
+        }
+    })
+}
+
+// Forwards the lines of the port until it fails, e.g. when the adapter is unplugged, or until `done`
+fn read_lines(port: impl Read, formats: &[SerialFormat], filter: &mut InputFilter, sender: &Sender<Request>, done: impl Fn() -> bool) {
+    let mut reader = BufReader::new(port);
+    // Kept across timeouts, which can end a read in the middle of a line
+    let mut line = String::new();
+
+    while !done() {
+        for event in filter.poll(Instant::now()) {
+            sender.send(event.into()).unwrap();
+        }
+
+        match reader.read_line(&mut line) {
+            Ok(0) => return,
+            Ok(_) if !line.ends_with('\n') => continue,
+            Ok(_) => {
+                let arrived_at = Instant::now();
+
+                match parse_line(formats, line.trim()) {
+                    Some((button_id, pressed)) => {
+                        for event in filter.edge(button_id, pressed, arrived_at) {
+                            sender.send(event.into()).unwrap();
+                        }
+                    },
+                    None if line.trim().is_empty() => (),
+                    None => eprintln!("Unknown serial line {:?}", line.trim()),
+                }
+
+                line.clear();
+            },
+            Err(ref e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => (),
+            Err(e) => {
+                eprintln!("Error reading from serial port: {}", e);
+                return;
+            },
+        }
+    }
+}
+
+// io_bank_num and press state of the first format matching the line
+fn parse_line(formats: &[SerialFormat], line: &str) -> Option<(u8, bool)> {
+    formats
+        .iter()
+        // Without the placeholder there's no telling the buttons apart
+        .filter(|format| format.pattern.contains("{button}"))
+        .find_map(|format| match_pattern(&format.pattern, line).map(|button_id| (button_id, format.pressed)))
+}
+
+fn match_pattern(pattern: &str, line: &str) -> Option<u8> {
+    if let Some(rest) = pattern.strip_prefix("{button}") {
+        let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        // Longest number first, shorter ones only leave room for digits the rest of the pattern needs
+        return (1..=digits).rev().find_map(|len| {
+            let button_id = line[..len].parse().ok()?;
+            match_pattern(rest, &line[len..]).map(|_| button_id)
+        });
+    }
+
+    if let Some(rest) = pattern.strip_prefix("{*}") {
+        return line
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([line.len()])
+            .find_map(|i| match_pattern(rest, &line[i..]));
+    }
+
+    match pattern.chars().next() {
+        // Matched, the button was captured further up
+        None => line.is_empty().then_some(0),
+        Some(c) => match_pattern(&pattern[c.len_utf8()..], line.strip_prefix(c)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread::sleep;
+
+    use serialport::{SerialPort, TTYPort};
+
+    fn format(pattern: &str, pressed: bool) -> SerialFormat {
+        SerialFormat { pattern: pattern.to_string(), pressed }
+    }
+
+    #[test]
+    fn lines_match_the_formats() {
+        let formats = [format("BTN{button} DOWN", true), format("BTN{button} UP", false), format("{*};{button};{*}", true)];
+
+        assert_eq!(parse_line(&formats, "BTN3 DOWN"), Some((3, true)));
+        assert_eq!(parse_line(&formats, "BTN12 UP"), Some((12, false)));
+        assert_eq!(parse_line(&formats, "123456;7;press"), Some((7, true)));
+        assert_eq!(parse_line(&formats, "BTN DOWN"), None);
+        assert_eq!(parse_line(&formats, "BTN300 DOWN"), None);
+        assert_eq!(parse_line(&[format("{button}", true)], "4"), Some((4, true)));
+        assert_eq!(parse_line(&[format("{button}{*}", true)], "12X"), Some((12, true)));
+        assert_eq!(parse_line(&[format("{button}5", true)], "125"), Some((12, true)));
+    }
+
+    #[test]
+    fn presses_are_read_from_a_pseudo_terminal() {
+        let (mut buzzer, mut port) = TTYPort::pair().unwrap();
+        port.set_timeout(Duration::from_millis(100)).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        let formats = [format("P{button}", true), format("R{button}", false)];
+        let reader = spawn(move || read_lines(port, &formats, &mut InputFilter::default(), &sender, || false));
+
+        buzzer.write_all(b"P2\r\nnoise\r\nR").unwrap();
+        sleep(Duration::from_millis(300));
+        buzzer.write_all(b"2\r\n").unwrap();
+        sleep(Duration::from_millis(300));
+        // Unplugging the adapter ends the reader
+        drop(buzzer);
+        reader.join().unwrap();
+
+        let buttons: Vec<_> = receiver.try_iter()
+            .map(|request| match request.event {
+                InputEvent::SetButtonState(button_id, pressed, Some(_)) => (button_id, pressed),
+                event => panic!("Unexpected {:?}", event),
+            })
+            .collect();
+        assert_eq!(buttons, vec![(2, true), (2, false)]);
+    }
+
+    #[test]
+    fn changed_settings_end_the_reader() {
+        let (_buzzer, mut port) = TTYPort::pair().unwrap();
+        port.set_timeout(Duration::from_millis(100)).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        let changed = Arc::new(AtomicBool::new(false));
+
+        let done = changed.clone();
+        let reader = spawn(move || read_lines(port, &[], &mut InputFilter::default(), &sender, || done.load(Ordering::Relaxed)));
+
+        sleep(Duration::from_millis(200));
+        assert!(!reader.is_finished());
+        changed.store(true, Ordering::Relaxed);
+        sleep(Duration::from_millis(300));
+        assert!(reader.is_finished());
+    }
+}
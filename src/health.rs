@@ -0,0 +1,57 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+// Only the latest faults are kept, a failing box can report many per second
+const MAX_FAULTS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FaultKind {
+    Overcurrent,
+    CriticalError,
+    // A message from cringed that couldn't be read
+    TransportError,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Fault {
+    pub kind: FaultKind,
+    pub io_bank_num: u8,
+    pub at: DateTime<Utc>,
+}
+
+impl Fault {
+    pub fn describe(&self) -> String {
+        format!("{:?} on io bank {} at {}", self.kind, self.io_bank_num, self.at.format("%H:%M:%S"))
+    }
+}
+
+// Faults reported by the button hardware since an operator last acknowledged them
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HardwareHealth {
+    pub faults: Vec<Fault>,
+    // Including the ones dropped because of MAX_FAULTS
+    pub fault_count: usize,
+}
+
+impl HardwareHealth {
+    pub fn record(&mut self, fault: Fault) {
+        if self.faults.len() == MAX_FAULTS {
+            self.faults.remove(0);
+        }
+
+        self.faults.push(fault);
+        self.fault_count += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.faults.clear();
+        self.fault_count = 0;
+    }
+
+    #[cfg(feature = "display")]
+    pub fn last_fault(&self) -> Option<&Fault> {
+        self.faults.last()
+    }
+}
@@ -1,17 +1,37 @@
 use std::process::Command;
 use std::io::Result;
 
-#[derive(Debug, Clone)]
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Info {
     pub ips: Vec<String>,
     pub number_displays: usize,
+    pub config: Config,
+    pub buttons: ButtonStatus,
+}
+
+// Connection to the cringed button daemon, which is retried every second while it's down
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ButtonStatus {
+    pub connected: bool,
+    // When the connection was last made or lost
+    pub changed_at: Option<DateTime<Utc>>,
+    pub last_event_at: Option<DateTime<Utc>>,
 }
 
 impl Info {
-    pub fn get() -> Result<Self> {
+    pub fn get(config: Config) -> Result<Self> {
         let info = Info {
             ips: get_ips()?,
             number_displays: get_number_displays()?,
+            config,
+            buttons: ButtonStatus::default(),
         };
 
         Ok(info)
@@ -20,7 +40,11 @@ impl Info {
 
 pub fn get_number_displays() -> Result<usize> {
     if cfg!(target_os = "linux") {
-        let output = Command::new("xrandr").output()?;
+        // No X server (e.g. headless), so no displays to report
+        let output = match Command::new("xrandr").output() {
+            Ok(output) => output,
+            Err(_) => return Ok(0),
+        };
 
         let stdout = String::from_utf8_lossy(&output.stdout);
         let displays = stdout
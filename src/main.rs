@@ -1,3 +1,4 @@
+#[cfg(feature = "display")]
 mod display;
 mod timer;
 mod state;
@@ -7,44 +8,110 @@ mod broadcast;
 mod gpio;
 mod info;
 mod settings;
+mod runs;
+mod athletes;
+mod competition;
+mod clock;
+mod config;
+mod schema;
+mod auth;
+mod health;
 
 use std::thread;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 
+#[cfg(feature = "display")]
 use display::Display;
-use state::{StateManager};
+use state::{StateManager, OutputEvent, Request};
 use web::spawn_server;
-use gpio::spawn_gpio;
+use gpio::{spawn_input, SharedInputSettings};
+use runs::RunHistory;
+use athletes::Roster;
+use config::Config;
+
+const STATE_UPDATE_INTERVAL: Duration = Duration::from_millis(10);
 
 pub fn main() -> Result<(), String> {
+    let config = Config::from_args().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    config.apply();
+
     wait_for_network();
-    std::thread::sleep(std::time::Duration::from_secs(5));
+    std::thread::sleep(std::time::Duration::from_secs(config.startup_delay));
 
     let (input_tx, input_rx) = mpsc::channel();
     let (output_tx, output_rx) = mpsc::channel();
-    let _server_handle = spawn_server(input_tx.clone(), output_rx);
+    let history = Arc::new(Mutex::new(RunHistory::open()));
+    let roster = Arc::new(Mutex::new(Roster::open()));
+    let server_handle = spawn_server(input_tx.clone(), output_rx, history.clone(), roster.clone(), config.clone());
 
-    let (display_tx, display_rx) = mpsc::channel();
+    let (display_tx, display_rx) = if is_headless(&config) {
+        println!("Running headless");
+        (None, None)
+    } else {
+        let (tx, rx) = mpsc::channel();
+        (Some(tx), Some(rx))
+    };
 
-    let _gpio_handle = spawn_gpio(input_tx);
+    let input_settings = SharedInputSettings::default();
+    let input_handle = spawn_input(input_tx.clone(), &config, &input_settings).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
 
     let _state_handle = thread::spawn(move || {
-        let mut state_manager = StateManager::new();
-        state_manager.add_listener(display_tx);
+        let mut state_manager = StateManager::new(&config, history, roster);
+        state_manager.share_input_settings(input_settings);
+        if let Some(display_tx) = display_tx {
+            state_manager.add_listener(display_tx);
+        }
         state_manager.add_listener(output_tx);
 
         state_manager.sync_all().unwrap();
-        for event in input_rx {
-            state_manager.process(event).unwrap();
+        loop {
+            match input_rx.recv_timeout(STATE_UPDATE_INTERVAL) {
+                Ok(request) => state_manager.handle(request),
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            if let Err(e) = state_manager.update() {
+                eprintln!("Couldn't update timers: {}", e);
+            }
         }
     });
 
-    let mut display = Display::new(display_rx);
-    display.show_windows().unwrap();
+    match display_rx {
+        Some(display_rx) => show_display(display_rx, input_tx)?,
+        // The web server stops on SIGINT/SIGTERM, which ends a headless run
+        None => server_handle.join().map_err(|_| String::from("Web server thread panicked"))?,
+    }
+
+    if let Some(input_handle) = input_handle {
+        input_handle.stop();
+    }
 
     Ok(())
 }
 
+fn is_headless(config: &Config) -> bool {
+    cfg!(not(feature = "display")) || config.headless
+}
+
+#[cfg(feature = "display")]
+fn show_display(receiver: mpsc::Receiver<OutputEvent>, sender: mpsc::Sender<Request>) -> Result<(), String> {
+    let mut display = Display::new(receiver, sender);
+    display.show_windows()
+}
+
+#[cfg(not(feature = "display"))]
+fn show_display(_receiver: mpsc::Receiver<OutputEvent>, _sender: mpsc::Sender<Request>) -> Result<(), String> {
+    Err(String::from("Built without the display feature"))
+}
+
 fn wait_for_network() {
     let mut counter = 0;
 
@@ -0,0 +1,154 @@
+use std::io::{ErrorKind, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::config::{config_path, move_aside};
+use crate::timer::Timer;
+use crate::athletes::Athlete;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Run {
+    pub id: u64,
+    pub lane: usize,
+    pub started_at: DateTime<Utc>,
+    pub stopped_at: DateTime<Utc>,
+    pub elapsed_millis: i128,
+    pub countdown: u64,
+    #[serde(default)]
+    pub athlete: Option<Athlete>,
+    #[serde(default)]
+    pub dnf: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunHistory {
+    next_id: u64,
+    runs: Vec<Run>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl RunHistory {
+    // Loads the stored run history, starting empty if there isn't one yet
+    pub fn open() -> Self {
+        match Self::load() {
+            Ok(mut history) => {
+                history.path = Some(Self::get_path());
+                history
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Self {
+                path: Some(Self::get_path()),
+                ..Self::default()
+            },
+            Err(e) => {
+                eprintln!("Couldn't load run history: {}", e);
+                // Keep the old results around instead of overwriting them with the next run
+                match move_aside(&Self::get_path()) {
+                    Ok(path) => {
+                        eprintln!("Moved the unreadable run history to {}", path.display());
+                        Self {
+                            path: Some(Self::get_path()),
+                            ..Self::default()
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Couldn't move the run history aside, new runs won't be saved: {}", e);
+                        Self::default()
+                    }
+                }
+            }
+        }
+    }
+
+    fn load() -> Result<Self> {
+        let history_string = fs::read_to_string(Self::get_path())?;
+        serde_json::from_str(&history_string).map_err(Into::into)
+    }
+
+    // Does nothing for a run history that wasn't opened from disk
+    pub fn save(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let json = serde_json::to_string(self)?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, json)
+    }
+
+    // Stores the result of a stopped timer, returns None if the timer hasn't finished
+    pub fn record(&mut self, lane: usize, timer: &Timer) -> Option<&Run> {
+        let run = Run {
+            id: self.next_id,
+            lane,
+            started_at: timer.started_at_datetime?,
+            stopped_at: timer.stopped_at_datetime?,
+            elapsed_millis: timer.as_millis(),
+            countdown: timer.countdown_duration.as_secs(),
+            athlete: timer.athlete.clone(),
+            dnf: timer.is_dnf(),
+        };
+
+        self.next_id += 1;
+        self.runs.push(run);
+        self.runs.last()
+    }
+
+    pub fn runs(&self) -> &[Run] {
+        &self.runs
+    }
+
+    pub fn delete(&mut self, id: u64) -> Option<Run> {
+        let index = self.runs.iter().position(|run| run.id == id)?;
+        Some(self.runs.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.runs.clear();
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("id,lane,started_at,stopped_at,elapsed_millis,countdown,athlete,bib,category,dnf\n");
+
+        for run in &self.runs {
+            let (name, bib, category) = match run.athlete {
+                Some(ref athlete) => (csv_field(&athlete.name), athlete.bib.to_string(), csv_field(&athlete.category)),
+                None => (String::new(), String::new(), String::new()),
+            };
+
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{}\n",
+                run.id,
+                run.lane,
+                run.started_at.to_rfc3339(),
+                run.stopped_at.to_rfc3339(),
+                run.elapsed_millis,
+                run.countdown,
+                name,
+                bib,
+                category,
+                run.dnf,
+            ));
+        }
+
+        csv
+    }
+
+    fn get_path() -> PathBuf {
+        config_path("runs.json")
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
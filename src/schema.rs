@@ -0,0 +1,142 @@
+use serde::Serialize;
+use chrono::Utc;
+
+use crate::athletes::Athlete;
+use crate::competition::Leaderboard;
+use crate::health::HardwareHealth;
+use crate::info::Info;
+use crate::settings::Settings;
+use crate::state::OutputEvent;
+use crate::timer::{Timer, TimerState};
+
+// Wire format of the output events sent over SSE (/api/events) and WebSocket (/api/ws).
+// Every payload carries the schema version, bump it whenever a field is renamed or removed.
+//
+//   syncTimers        { version, timers: [Timer], now }   now is the server time in unix millis
+//   syncSettings      { version, settings }           without the PINs
+//   syncInfo          { version, info: { ips, numberDisplays, config, buttons: { connected, changedAt, lastEventAt } } }
+//   syncLeaderboard   { version, leaderboard }
+//   reloadBackground  { version }
+//   setDisplay        { version, visible }
+//   setLeaderboard    { version, visible }
+//   syncHealth        { version, health: { faults: [{ kind, ioBankNum, at }], faultCount } }
+//
+// Control endpoints of the web API answer with the syncTimers payload.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimerPayload {
+    pub id: usize,
+    // Unix millis, None while the timer hasn't started or stopped
+    pub started_at: Option<i64>,
+    pub stopped_at: Option<i64>,
+    pub countdown: u64,
+    pub time_limit: Option<u64>,
+    pub state: TimerState,
+    pub formatted: String,
+    pub athlete: Option<Athlete>,
+    pub splits: Vec<i128>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Payload<'a> {
+    Timers { timers: Vec<TimerPayload>, now: i64 },
+    Settings { settings: Box<Settings> },
+    Info { info: &'a Info },
+    Leaderboard { leaderboard: &'a Leaderboard },
+    Visibility { visible: bool },
+    Health { health: &'a HardwareHealth },
+    Empty {},
+}
+
+#[derive(Debug, Serialize)]
+pub struct Message<'a> {
+    pub version: u32,
+    #[serde(flatten)]
+    pub payload: Payload<'a>,
+}
+
+// Event name and payload of an output event
+pub fn encode(event: &OutputEvent) -> (&'static str, Message<'_>) {
+    let (name, payload) = match event {
+        OutputEvent::SyncTimers(timers) => ("syncTimers", timers_payload(timers)),
+        OutputEvent::SyncSettings(settings) => {
+            // Spectators receive the settings too, so the PINs never leave the server
+            let settings = Settings {
+                operator_pin: None,
+                admin_pin: None,
+                ..(**settings).clone()
+            };
+
+            ("syncSettings", Payload::Settings { settings: Box::new(settings) })
+        },
+        OutputEvent::SyncInfo(info) => ("syncInfo", Payload::Info { info }),
+        OutputEvent::SyncLeaderboard(leaderboard) => ("syncLeaderboard", Payload::Leaderboard { leaderboard }),
+        OutputEvent::ReloadBackground => ("reloadBackground", Payload::Empty {}),
+        OutputEvent::SetDisplay(visible) => ("setDisplay", Payload::Visibility { visible: *visible }),
+        OutputEvent::SetLeaderboard(visible) => ("setLeaderboard", Payload::Visibility { visible: *visible }),
+        OutputEvent::SyncHealth(health) => ("syncHealth", Payload::Health { health }),
+    };
+
+    (name, Message { version: SCHEMA_VERSION, payload })
+}
+
+// Timers as sent by syncTimers
+pub fn timers(timers: &[Timer]) -> Message<'static> {
+    Message { version: SCHEMA_VERSION, payload: timers_payload(timers) }
+}
+
+fn timers_payload(timers: &[Timer]) -> Payload<'static> {
+    let timers = timers.iter().enumerate().map(|(i, timer)| TimerPayload {
+        id: i,
+        started_at: timer.started_at_datetime.map(|x| x.timestamp_millis()),
+        stopped_at: timer.stopped_at_datetime.map(|x| x.timestamp_millis()),
+        countdown: timer.countdown_duration.as_secs(),
+        time_limit: timer.time_limit.map(|x| x.as_secs()),
+        state: timer.get_state(),
+        formatted: timer.format(),
+        athlete: timer.athlete.clone(),
+        splits: timer.split_millis(),
+    }).collect();
+
+    Payload::Timers { timers, now: Utc::now().timestamp_millis() }
+}
+
+pub fn to_json(event: &OutputEvent) -> (&'static str, String) {
+    let (name, message) = encode(event);
+    (name, serde_json::to_string(&message).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads_carry_the_schema_version() {
+        assert_eq!(to_json(&OutputEvent::SetDisplay(true)), ("setDisplay", r#"{"version":1,"visible":true}"#.to_string()));
+        assert_eq!(to_json(&OutputEvent::ReloadBackground), ("reloadBackground", r#"{"version":1}"#.to_string()));
+    }
+
+    #[test]
+    fn settings_are_nested_under_their_name() {
+        let (name, json) = to_json(&OutputEvent::SyncSettings(Box::default()));
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(name, "syncSettings");
+        assert_eq!(value["version"], 1);
+        assert_eq!(value["settings"]["lanes"], 2);
+    }
+
+    #[test]
+    fn pins_are_not_sent() {
+        let settings = Settings {
+            operator_pin: Some("1234".to_string()),
+            ..Settings::default()
+        };
+
+        let (_, json) = to_json(&OutputEvent::SyncSettings(Box::new(settings)));
+        assert!(!json.contains("1234"));
+    }
+}
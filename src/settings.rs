@@ -1,45 +1,273 @@
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
 use std::fs;
-use std::env;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+use crate::config::{config_path, move_aside};
+
+// How the start button and the countdown drive the lanes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LaneMode {
+    // Every lane starts and resets together, like in a race
+    #[default]
+    Synchronized,
+    // Each lane is started and reset on its own, e.g. for training
+    Independent,
+}
+
+// Buttons acting on the whole installation rather than on a single lane
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ButtonRole {
+    // Starts the timers like the start button of the web UI, e.g. a start button or start mat
+    Start,
+    Reset,
+    ToggleDisplay,
+    ToggleDebug,
+}
+
+// What a key of a keyboard, foot pedal or presenter clicker on the display machine does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub enum KeyAction {
+    Start,
+    Reset,
+    // Acts as the stop button of the lane, indexed from 0
+    StopLane(usize),
+    ToggleDisplay,
+    ToggleDebug,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyBinding {
+    // SDL key name, e.g. "Space", "F5" or "PageDown"
+    pub key: String,
+    pub action: KeyAction,
+}
+
+// Filtering of a contact button in the input layer, windows in milliseconds
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ButtonFilter {
+    pub io_bank_num: u8,
+    // Edges this soon after the previous one are contact bounce
+    pub debounce_ms: u64,
+    // Presses released sooner are dropped, longer ones count from when they were pressed
+    pub min_hold_ms: u64,
+    // Presses this soon after the last one are dropped, e.g. an athlete slapping the buzzer twice
+    pub ignore_after_stop_ms: u64,
+}
+
+// Line sent by a serial buzzer, "{button}" stands for the io_bank_num and "{*}" for any text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerialFormat {
+    pub pattern: String,
+    // Whether the line is a press or a release
+    pub pressed: bool,
+}
+
+// Buzzers behind a USB-serial adapter, read when the serial input is selected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SerialSettings {
+    pub port: String,
+    pub baud: u32,
+    // Tried in order, lines matching none of them are logged and dropped
+    pub formats: Vec<SerialFormat>,
+}
+
+impl Default for SerialSettings {
+    fn default() -> Self {
+        SerialSettings {
+            port: "/dev/ttyUSB0".to_string(),
+            baud: 9600,
+            formats: vec![SerialFormat { pattern: "{button}".to_string(), pressed: true }],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
 pub struct Settings {
     pub countdown: u64,
+    // Countdown in seconds for each lane, indexed by lane, None uses the common countdown
+    pub lane_countdowns: Vec<Option<u64>>,
+    pub lane_mode: LaneMode,
     pub show_debug: bool,
     pub fullscreen: bool,
+    pub lanes: usize,
+    // io_bank_num of the stop button for each lane, indexed by lane, None leaves a lane without one
+    pub lane_buttons: Vec<Option<u8>>,
+    // io_bank_num of the checkpoint button for each lane, indexed by lane
+    pub split_buttons: Vec<Option<u8>>,
+    // Lane or start mat input during the countdown disqualifies the lane
+    pub false_start_detection: bool,
+    // io_bank_num of the start mat sensor for each lane, released when the athlete steps off
+    pub start_mat_buttons: Vec<Option<u8>>,
+    // io_bank_num of the buttons for each ButtonRole
+    pub start_buttons: Vec<u8>,
+    pub reset_buttons: Vec<u8>,
+    pub display_buttons: Vec<u8>,
+    pub debug_buttons: Vec<u8>,
+    // Buttons without a filter are passed on as they are
+    pub button_filters: Vec<ButtonFilter>,
+    pub key_bindings: Vec<KeyBinding>,
+    pub serial: SerialSettings,
+    // Maximum run time in seconds, lanes still running after it are marked DNF
+    pub time_limit: Option<u64>,
+    // Refuse to start while the button daemon is disconnected, otherwise it's only logged
+    pub require_buttons: bool,
+    // PINs of the web API roles, leaving both unset keeps the API open
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operator_pin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_pin: Option<String>,
+    // Where the settings were loaded from, None keeps them in memory only
+    #[serde(skip)]
+    pub(crate) path: Option<PathBuf>,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
             countdown: 3,
+            lane_countdowns: vec![],
+            lane_mode: LaneMode::Synchronized,
             show_debug: false,
             fullscreen: true,
+            lanes: 2,
+            lane_buttons: vec![Some(1), Some(2), Some(3), Some(4)],
+            split_buttons: vec![],
+            false_start_detection: false,
+            start_mat_buttons: vec![],
+            start_buttons: vec![],
+            reset_buttons: vec![],
+            display_buttons: vec![],
+            debug_buttons: vec![9],
+            // Keeps a bouncing debug button from flipping the overlay twice
+            button_filters: vec![ButtonFilter { io_bank_num: 9, ignore_after_stop_ms: 500, ..ButtonFilter::default() }],
+            key_bindings: vec![],
+            serial: SerialSettings::default(),
+            time_limit: None,
+            require_buttons: false,
+            operator_pin: None,
+            admin_pin: None,
+            path: None,
         }
     }
 }
 
 impl Settings {
+    pub const MAX_LANES: usize = 8;
+
+    // Loads the stored settings, writing the defaults if there aren't any yet
+    pub fn open() -> Self {
+        match Self::load() {
+            Ok(settings) => return settings,
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => {
+                eprintln!("Couldn't load settings, using defaults: {}", e);
+                // Keep a broken settings file around so it can be fixed by hand
+                match move_aside(&Self::get_path()) {
+                    Ok(path) => eprintln!("Moved the broken settings to {}", path.display()),
+                    Err(e) => {
+                        eprintln!("Couldn't move the broken settings aside, changes won't be saved: {}", e);
+                        return Self::default();
+                    },
+                }
+            },
+        }
+
+        let settings = Self {
+            path: Some(Self::get_path()),
+            ..Self::default()
+        };
+        if let Err(e) = settings.save() {
+            eprintln!("Couldn't save settings: {}", e);
+        }
+        settings
+    }
+
     // load from file
     pub fn load() -> Result<Self> {
         let settings_string = fs::read_to_string(Self::get_path())?;
-        Ok(serde_json::from_str(&settings_string).unwrap())
+        let mut settings: Self = serde_json::from_str(&settings_string).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        settings.path = Some(Self::get_path());
+        Ok(settings)
+    }
+
+    pub fn countdown_for(&self, lane: usize) -> u64 {
+        self.lane_countdowns
+            .get(lane)
+            .copied()
+            .flatten()
+            .unwrap_or(self.countdown)
+    }
+
+    pub fn lane_for_button(&self, button_id: u8) -> Option<usize> {
+        self.find_lane(&self.lane_buttons, button_id)
+    }
+
+    pub fn split_lane_for_button(&self, button_id: u8) -> Option<usize> {
+        self.find_lane(&self.split_buttons, button_id)
+    }
+
+    pub fn start_mat_lane_for_button(&self, button_id: u8) -> Option<usize> {
+        self.find_lane(&self.start_mat_buttons, button_id)
+    }
+
+    pub fn has_role(&self, button_id: u8, role: ButtonRole) -> bool {
+        self.role_buttons(role).contains(&button_id)
+    }
+
+    pub fn role_buttons(&self, role: ButtonRole) -> &Vec<u8> {
+        match role {
+            ButtonRole::Start => &self.start_buttons,
+            ButtonRole::Reset => &self.reset_buttons,
+            ButtonRole::ToggleDisplay => &self.display_buttons,
+            ButtonRole::ToggleDebug => &self.debug_buttons,
+        }
+    }
+
+    pub fn role_buttons_mut(&mut self, role: ButtonRole) -> &mut Vec<u8> {
+        match role {
+            ButtonRole::Start => &mut self.start_buttons,
+            ButtonRole::Reset => &mut self.reset_buttons,
+            ButtonRole::ToggleDisplay => &mut self.display_buttons,
+            ButtonRole::ToggleDebug => &mut self.debug_buttons,
+        }
+    }
+
+    #[cfg(feature = "display")]
+    pub fn key_action(&self, key: &str) -> Option<KeyAction> {
+        self.key_bindings
+            .iter()
+            .find(|binding| binding.key.eq_ignore_ascii_case(key))
+            .map(|binding| binding.action)
     }
 
+    fn find_lane(&self, buttons: &[Option<u8>], button_id: u8) -> Option<usize> {
+        buttons
+            .iter()
+            .position(|&button| button == Some(button_id))
+            .filter(|&lane| lane < self.lanes)
+    }
+
+    // Does nothing for settings that weren't loaded from disk
     pub fn save(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
         let json = serde_json::to_string(self).unwrap();
-        let path = Self::get_path();
         fs::create_dir_all(path.parent().unwrap())?;
         fs::write(path, json)
     }
 
     fn get_path() -> PathBuf {
-        let home = env::var("HOME").unwrap();
-        let home_path = Path::new(&home);
-        home_path.join(".config/ninja-timer/settings.json")
+        config_path("settings.json")
     }
 }
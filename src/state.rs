@@ -1,34 +1,128 @@
-use std::sync::mpsc;
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex};
 use std::time::{Instant, Duration};
 
 use actix_web::cookie::time::Time;
+use serde::Deserialize;
 
 use crate::timer::{Timer, TimerState};
-use crate::settings::Settings;
+use crate::settings::{ButtonFilter, ButtonRole, KeyBinding, LaneMode, SerialSettings, Settings};
+#[cfg(feature = "display")]
+use crate::settings::KeyAction;
 use crate::info::Info;
+use crate::runs::RunHistory;
+use crate::athletes::Roster;
+use crate::competition::{Competition, Heat, Leaderboard};
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::health::{Fault, FaultKind, HardwareHealth};
+use crate::gpio::{InputSettings, SharedInputSettings};
 
-#[derive(Debug)]
+// A Start press this soon after the start is a bounce or a double click, not a reset
+const MIN_RUN_BEFORE_RESET: Duration = Duration::from_secs(1);
+
+// Button edges only refresh the info this often, the last one is sent on the next update
+const BUTTON_INFO_INTERVAL: Duration = Duration::from_secs(1);
+
+// Also the message format of the WebSocket control channel, e.g. "StartTimers" or {"StopTimer": 1}
+#[derive(Debug, Deserialize)]
 pub enum InputEvent {
     StartTimers,
+    // Single lanes only start and reset on their own in independent lane mode
+    StartTimer(usize),
     StopTimer(usize),
     StopTimers,
+    Split(usize),
     ResetTimers,
+    ResetTimer(usize),
     RequestSync,
-    SetButtonState(u8, bool),
+    // io_bank_num, whether it's pressed and when it was pressed according to the hardware,
+    // None (and always for WebSocket clients) means now
+    SetButtonState(u8, bool, #[serde(skip)] Option<Instant>),
     SetDebug(bool),
     SetCountdown(u64),
+    SetLaneCountdown(usize, Option<u64>),
+    SetLaneMode(LaneMode),
+    SetLanes(usize),
+    SetLaneButton(usize, Option<u8>),
+    SetSplitButton(usize, Option<u8>),
+    SetStartMatButton(usize, Option<u8>),
+    SetRoleButtons(ButtonRole, Vec<u8>),
+    SetKeyBindings(Vec<KeyBinding>),
+    // Applied by the input backends from the next button edge on
+    SetButtonFilters(Vec<ButtonFilter>),
+    // The serial input opens the port again with the new settings
+    SetSerial(SerialSettings),
+    SetFalseStartDetection(bool),
+    SetRequireButtons(bool),
+    SetTimeLimit(Option<u64>),
+    // Operator and admin PIN, None removes the PIN
+    SetPins(Option<String>, Option<String>),
+    AssignAthlete(usize, Option<u64>),
+    StartCompetition(Vec<Heat>),
+    EndCompetition,
     ReloadBackground,
     ToggleDisplay,
     ToggleDebug,
+    ToggleLeaderboard,
+    // Fault reported by the button hardware for an io_bank_num, never sent by clients
+    #[serde(skip)]
+    ReportFault(FaultKind, u8),
+    // Connection of the button daemon was made or lost, never sent by clients
+    #[serde(skip)]
+    SetButtonsConnected(bool),
+    // Key pressed on the display machine, by SDL key name, mapped with the key bindings
+    #[cfg(feature = "display")]
+    #[serde(skip)]
+    PressKey(String, Option<Instant>),
+    ClearFaults,
+}
+
+// Why an event couldn't be processed, the web API answers with a matching status code
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    // A lane, timer or athlete that doesn't exist
+    NotFound(String),
+    // A value out of range
+    Invalid(String),
+    // An event that doesn't fit the current state, e.g. assigning an athlete to a running lane
+    Conflict(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotFound(msg) | StateError::Invalid(msg) | StateError::Conflict(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+// Sends the timers as they are after an event, or why it failed, back to whoever asked for it
+pub type Reply = mpsc::Sender<Result<Vec<Timer>, StateError>>;
+
+// What the state thread receives, input from buttons doesn't wait for a reply
+#[derive(Debug)]
+pub struct Request {
+    pub event: InputEvent,
+    pub reply: Option<Reply>,
+}
+
+impl From<InputEvent> for Request {
+    fn from(event: InputEvent) -> Self {
+        Request { event, reply: None }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum OutputEvent {
     SyncTimers(Vec<Timer>),
-    SyncSettings(Settings),
-    SyncInfo(Info),
+    SyncSettings(Box<Settings>),
+    SyncInfo(Box<Info>),
+    SyncLeaderboard(Leaderboard),
     ReloadBackground,
     SetDisplay(bool),
+    SetLeaderboard(bool),
+    SyncHealth(HardwareHealth),
 }
 
 pub struct StateManager {
@@ -36,32 +130,58 @@ pub struct StateManager {
     timers: Vec<Timer>,
     settings: Settings,
     info: Info,
+    history: Arc<Mutex<RunHistory>>,
+    roster: Arc<Mutex<Roster>>,
+    competition: Competition,
+    health: HardwareHealth,
+    input_settings: SharedInputSettings,
+    clock: Arc<dyn Clock>,
     reset_at: Instant,
     started_at: Instant,
-    toggled_debug_at: Instant,
+    // Last button edge not sent to the listeners yet, and when the info was last sent for one
+    button_info_pending: bool,
+    button_info_synced_at: Option<Instant>,
     display_visible: bool,
+    leaderboard_visible: bool,
 }
 
 impl StateManager {
 
-    pub fn new() -> Self {
-        let settings = Settings::load().unwrap_or_else(|_| {
-            let default = Settings::default();
-            default.save().unwrap();
-            default
-        });
+    pub fn new(config: &Config, history: Arc<Mutex<RunHistory>>, roster: Arc<Mutex<Roster>>) -> Self {
+        let settings = Settings::open();
 
-        let info = Info::get().unwrap();
+        let info = Info::get(config.clone()).unwrap();
+
+        Self::with_clock(settings, info, history, roster, Competition::open(), Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(
+        settings: Settings,
+        info: Info,
+        history: Arc<Mutex<RunHistory>>,
+        roster: Arc<Mutex<Roster>>,
+        competition: Competition,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let now = clock.now();
 
         Self {
             listeners: vec![],
-            timers: (0..2).map(|_| Timer::new(settings.countdown)).collect(),
+            timers: create_timers(&settings, &clock),
             settings,
             info,
-            reset_at: Instant::now(),
-            started_at: Instant::now(),
-            toggled_debug_at: Instant::now(),
+            history,
+            roster,
+            competition,
+            health: HardwareHealth::default(),
+            input_settings: SharedInputSettings::default(),
+            clock,
+            reset_at: now,
+            started_at: now,
+            button_info_pending: false,
+            button_info_synced_at: None,
             display_visible: true,
+            leaderboard_visible: false,
         }
     }
 
@@ -69,82 +189,341 @@ impl StateManager {
         self.listeners.push(listener);
     }
 
-    pub fn process(&mut self, event: InputEvent) -> Result<(), String> {
-        match event {
-            InputEvent::StartTimers => {
-                let is_reset = self.get_timer_mut(0)?.get_state() == TimerState::Reset;
+    // Keeps the settings of the input threads up to date from now on
+    pub fn share_input_settings(&mut self, input_settings: SharedInputSettings) {
+        self.input_settings = input_settings;
+        self.publish_input_settings();
+    }
 
-                if is_reset && Instant::now() - self.reset_at > Duration::from_secs(1) {
-                    for (i, timer) in self.timers.iter_mut().enumerate() {
-                        if let Err(msg) = timer.start() {
-                            eprintln!("Timer {} couldn't be started: {}", i, msg);
-                        }
-                        self.started_at = Instant::now();
-                    }
+    fn publish_input_settings(&self) {
+        *self.input_settings.write().unwrap() = InputSettings::from_settings(&self.settings);
+    }
 
-                    self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
-                } else if Instant::now() - self.started_at > Duration::from_secs(1) {
-                    for timer in &mut self.timers {
-                        timer.reset();
-                        self.reset_at = Instant::now();
-                    }
-                    
-                    self.reset_at = Instant::now();
-                    self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+    // Processes a request, errors nobody waits for are only logged
+    pub fn handle(&mut self, request: Request) {
+        let result = self.process(request.event);
+
+        match request.reply {
+            Some(reply) => {
+                let _ = reply.send(result.map(|()| self.timers.clone()));
+            },
+            None => if let Err(e) = result {
+                eprintln!("Couldn't process event: {}", e);
+            },
+        }
+    }
+
+    pub fn process(&mut self, event: InputEvent) -> Result<(), StateError> {
+        match event {
+            InputEvent::StartTimers => {
+                // Keeps a double click in the web UI from resetting and starting again at once
+                if self.settings.lane_mode == LaneMode::Synchronized
+                    && self.get_timer_mut(0)?.get_state() == TimerState::Reset
+                    && self.clock.now() - self.reset_at <= Duration::from_secs(1)
+                {
+                    return Ok(());
                 }
 
+                self.start_timers(None)?;
+            },
+            InputEvent::StartTimer(i) => {
+                self.require_independent_lanes()?;
+                self.check_buttons()?;
+                self.get_timer_mut(i)?
+                    .start()
+                    .map_err(|msg| StateError::Conflict(format!("Timer {}: {}", i, msg)))?;
+                self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+            },
+            InputEvent::ResetTimer(i) => {
+                self.require_independent_lanes()?;
+                self.get_timer_mut(i)?.reset();
+                self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
             },
             InputEvent::StopTimer(i) => {
-                if let Err(msg) = self.get_timer_mut(i)?.stop() {
-                    eprintln!("Timer {} couldn't be stopped: {}", i, msg);
-                } else {
-                    self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
-                }
+                self.stop_timer(i, None)?
+                    .map_err(|msg| StateError::Conflict(format!("Timer {}: {}", i, msg)))?;
+                self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+            },
+            InputEvent::Split(i) => {
+                self.get_timer_mut(i)?
+                    .split()
+                    .map_err(|msg| StateError::Conflict(format!("Timer {}: {}", i, msg)))?;
+                self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
             },
             InputEvent::StopTimers => {
-                for timer in self.timers.iter_mut() {
-                    timer.stop()?;
+                for i in 0..self.timers.len() {
+                    if let Err(msg) = self.stop_timer(i, None)? {
+                        eprintln!("Timer {} couldn't be stopped: {}", i, msg);
+                    }
                 }
                 self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
             },
             InputEvent::ResetTimers => {
-                for timer in &mut self.timers {
-                    timer.reset();
-                }
-                
+                self.reset_timers()?;
                 self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
             },
             InputEvent::RequestSync => {
                 self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
-                self.notify_listeners(&OutputEvent::SyncSettings(self.settings.clone()))?;
-                self.notify_listeners(&OutputEvent::SyncInfo(self.info.clone()))?;
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+                self.notify_listeners(&OutputEvent::SyncInfo(Box::new(self.info.clone())))?;
+                self.notify_listeners(&OutputEvent::SyncLeaderboard(self.competition.leaderboard()))?;
+                self.notify_listeners(&OutputEvent::SetLeaderboard(self.leaderboard_visible))?;
+                self.notify_listeners(&OutputEvent::SyncHealth(self.health.clone()))?;
             },
-            InputEvent::SetButtonState(button_id, pressed) => {
-                if pressed && (button_id == 1 || button_id == 2) {
-                    if self.get_timer_mut((button_id - 1).into())?.stop().is_ok() {
+            InputEvent::SetButtonState(button_id, pressed, pressed_at) => {
+                // Only the button daemon knows when a button was pressed
+                if pressed_at.is_some() {
+                    self.info.buttons.last_event_at = Some(self.clock.utc_now());
+                    self.button_info_pending = true;
+                    self.sync_button_info()?;
+                }
+
+                if let (true, Some(lane)) = (pressed, self.settings.lane_for_button(button_id)) {
+                    self.press_lane_button(lane, pressed_at)?;
+                }
+
+                if let (false, Some(lane)) = (pressed, self.settings.start_mat_lane_for_button(button_id)) {
+                    if self.false_start(lane)? {
+                        self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+                    }
+                }
+
+                if let (true, Some(lane)) = (pressed, self.settings.split_lane_for_button(button_id)) {
+                    let timer = self.get_timer_mut(lane)?;
+                    let split = match pressed_at {
+                        Some(at) => timer.split_at(at),
+                        None => timer.split(),
+                    };
+
+                    if split.is_ok() {
                         self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
                     }
                 }
 
-                if button_id == 9 && Instant::now() - self.toggled_debug_at > Duration::from_secs_f32(0.5) {
-                    self.settings.show_debug = !self.settings.show_debug;
-                    self.notify_listeners(&OutputEvent::SyncSettings(self.settings.clone()))?;
-                    self.settings.save().unwrap();
-                    self.toggled_debug_at = Instant::now();
+                if !pressed {
+                    return Ok(());
+                }
+
+                if self.settings.has_role(button_id, ButtonRole::Start) {
+                    self.start_timers(pressed_at)?;
+                }
+
+                if self.settings.has_role(button_id, ButtonRole::Reset) {
+                    self.process(InputEvent::ResetTimers)?;
+                }
+
+                if self.settings.has_role(button_id, ButtonRole::ToggleDisplay) {
+                    self.process(InputEvent::ToggleDisplay)?;
+                }
+
+                if self.settings.has_role(button_id, ButtonRole::ToggleDebug) {
+                    self.process(InputEvent::ToggleDebug)?;
                 }
             },
             InputEvent::SetDebug(debug) => {
                 self.settings.show_debug = debug;
-                self.notify_listeners(&OutputEvent::SyncSettings(self.settings.clone()))?;
-                self.settings.save().unwrap();
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+                self.save_settings();
             },
             InputEvent::SetCountdown(countdown) => {
                 self.settings.countdown = countdown;
-                self.timers = (0..2).map(|_| Timer::new(self.settings.countdown)).collect();
-                self.notify_listeners(&OutputEvent::SyncSettings(self.settings.clone()))?;
+                self.rebuild_timers();
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+                self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetLaneCountdown(lane, countdown) => {
+                if lane >= Settings::MAX_LANES {
+                    return Err(StateError::NotFound(format!("Lane index {} is out of bounds", lane)));
+                }
+
+                // Changing the countdown of a running lane would shift its time
+                if let Some(timer) = self.timers.get(lane) {
+                    if timer.get_state() != TimerState::Reset {
+                        return Err(StateError::Conflict(format!("Timer {} hasn't been reset", lane)));
+                    }
+                }
+
+                if self.settings.lane_countdowns.len() <= lane {
+                    self.settings.lane_countdowns.resize(lane + 1, None);
+                }
+                self.settings.lane_countdowns[lane] = countdown;
+
+                let countdown = Duration::from_secs(self.settings.countdown_for(lane));
+                if let Some(timer) = self.timers.get_mut(lane) {
+                    timer.countdown_duration = countdown;
+                }
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+                self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetLaneMode(lane_mode) => {
+                self.settings.lane_mode = lane_mode;
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetLanes(lanes) => {
+                if lanes == 0 || lanes > Settings::MAX_LANES {
+                    return Err(StateError::Invalid(format!("Number of lanes must be between 1 and {}", Settings::MAX_LANES)));
+                }
+
+                self.settings.lanes = lanes;
+                self.rebuild_timers();
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+                self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetLaneButton(lane, button_id) => {
+                if lane >= Settings::MAX_LANES {
+                    return Err(StateError::NotFound(format!("Lane index {} is out of bounds", lane)));
+                }
+
+                set_lane_button(&mut self.settings.lane_buttons, lane, button_id);
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetSplitButton(lane, button_id) => {
+                if lane >= Settings::MAX_LANES {
+                    return Err(StateError::NotFound(format!("Lane index {} is out of bounds", lane)));
+                }
+
+                set_lane_button(&mut self.settings.split_buttons, lane, button_id);
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetStartMatButton(lane, button_id) => {
+                if lane >= Settings::MAX_LANES {
+                    return Err(StateError::NotFound(format!("Lane index {} is out of bounds", lane)));
+                }
+
+                set_lane_button(&mut self.settings.start_mat_buttons, lane, button_id);
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetRoleButtons(role, buttons) => {
+                *self.settings.role_buttons_mut(role) = buttons;
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetKeyBindings(key_bindings) => {
+                self.settings.key_bindings = key_bindings;
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetButtonFilters(button_filters) => {
+                self.settings.button_filters = button_filters;
+                self.publish_input_settings();
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetSerial(serial) => {
+                self.settings.serial = serial;
+                self.publish_input_settings();
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+
+                self.save_settings();
+            },
+            #[cfg(feature = "display")]
+            InputEvent::PressKey(key, pressed_at) => {
+                match self.settings.key_action(&key) {
+                    Some(KeyAction::Start) => self.start_timers(pressed_at)?,
+                    Some(KeyAction::Reset) => self.process(InputEvent::ResetTimers)?,
+                    Some(KeyAction::StopLane(lane)) => self.press_lane_button(lane, pressed_at)?,
+                    Some(KeyAction::ToggleDisplay) => self.process(InputEvent::ToggleDisplay)?,
+                    Some(KeyAction::ToggleDebug) => self.process(InputEvent::ToggleDebug)?,
+                    None => (),
+                }
+            },
+            InputEvent::SetTimeLimit(time_limit) => {
+                self.settings.time_limit = time_limit;
+                // Running lanes keep going under the new limit
+                for timer in &mut self.timers {
+                    timer.time_limit = time_limit.map(Duration::from_secs);
+                }
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
                 self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
 
-                self.settings.save().unwrap();
+                self.save_settings();
+            },
+            InputEvent::SetPins(operator_pin, admin_pin) => {
+                self.settings.operator_pin = operator_pin;
+                self.settings.admin_pin = admin_pin;
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetFalseStartDetection(enabled) => {
+                self.settings.false_start_detection = enabled;
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+
+                self.save_settings();
+            },
+            InputEvent::SetRequireButtons(enabled) => {
+                self.settings.require_buttons = enabled;
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+
+                self.save_settings();
+            },
+            InputEvent::AssignAthlete(lane, athlete_id) => {
+                let athlete = match athlete_id {
+                    Some(id) => match self.roster.lock().unwrap().get(id) {
+                        Some(athlete) => Some(athlete.clone()),
+                        None => return Err(StateError::NotFound(format!("Athlete {} doesn't exist", id))),
+                    },
+                    None => None,
+                };
+
+                let timer = self.get_timer_mut(lane)?;
+                if timer.get_state() != TimerState::Reset {
+                    return Err(StateError::Conflict(format!("Timer {} hasn't been reset", lane)));
+                }
+
+                timer.athlete = athlete;
+                self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+            },
+            InputEvent::StartCompetition(heats) => {
+                if heats.is_empty() {
+                    return Err(StateError::Invalid("Competition needs at least one heat".to_string()));
+                }
+
+                // Checked up front so a heat can't start with athletes missing from its lanes
+                {
+                    let roster = self.roster.lock().unwrap();
+                    for heat in &heats {
+                        if heat.lanes.len() > self.timers.len() {
+                            return Err(StateError::Invalid(format!("Heat has {} lanes, only {} are set up", heat.lanes.len(), self.timers.len())));
+                        }
+
+                        if let Some(id) = heat.lanes.iter().flatten().find(|&&id| roster.get(id).is_none()) {
+                            return Err(StateError::NotFound(format!("Athlete {} doesn't exist", id)));
+                        }
+                    }
+                }
+
+                for timer in &mut self.timers {
+                    timer.reset();
+                }
+
+                self.competition.start(heats);
+                self.assign_heat();
+                self.save_competition();
+                self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+                self.notify_listeners(&OutputEvent::SyncLeaderboard(self.competition.leaderboard()))?;
+            },
+            InputEvent::EndCompetition => {
+                self.competition.end();
+                self.save_competition();
+                self.notify_listeners(&OutputEvent::SyncLeaderboard(self.competition.leaderboard()))?;
             },
             InputEvent::ReloadBackground => {
                 self.notify_listeners(&OutputEvent::ReloadBackground)?;
@@ -153,34 +532,270 @@ impl StateManager {
                 self.display_visible = !self.display_visible;
                 self.notify_listeners(&OutputEvent::SetDisplay(self.display_visible))?;
             },
+            InputEvent::ToggleLeaderboard => {
+                self.leaderboard_visible = !self.leaderboard_visible;
+                self.notify_listeners(&OutputEvent::SetLeaderboard(self.leaderboard_visible))?;
+            },
+            InputEvent::ReportFault(kind, io_bank_num) => {
+                let fault = Fault { kind, io_bank_num, at: self.clock.utc_now() };
+                eprintln!("{} Hardware fault: {}", fault.at.to_rfc3339(), fault.describe());
+
+                self.health.record(fault);
+                self.notify_listeners(&OutputEvent::SyncHealth(self.health.clone()))?;
+            },
+            InputEvent::SetButtonsConnected(connected) => {
+                let buttons = &mut self.info.buttons;
+                buttons.connected = connected;
+                buttons.changed_at = Some(self.clock.utc_now());
+
+                let at = buttons.changed_at.unwrap().to_rfc3339();
+                if connected {
+                    eprintln!("{} Button daemon connected", at);
+                } else {
+                    eprintln!("{} Button daemon disconnected, buttons are offline", at);
+                }
+
+                self.notify_listeners(&OutputEvent::SyncInfo(Box::new(self.info.clone())))?;
+            },
+            InputEvent::ClearFaults => {
+                self.health.clear();
+                self.notify_listeners(&OutputEvent::SyncHealth(self.health.clone()))?;
+            },
             InputEvent::ToggleDebug => {
                 self.settings.show_debug = !self.settings.show_debug;
-                self.notify_listeners(&OutputEvent::SyncSettings(self.settings.clone()))?;
-                self.settings.save().unwrap();
+                self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+                self.save_settings();
             },
-            #[allow(unreachable_patterns)]
-            _ => return Err(format!("Couldn't process: {:?}", event)),
         }
 
         Ok(())
     }
 
-    pub fn sync_all(&mut self) -> Result<(), String> {
+    // Called periodically by the state thread to stop lanes that ran out of time
+    pub fn update(&mut self) -> Result<(), StateError> {
+        let mut changed = false;
+
+        for i in 0..self.timers.len() {
+            if self.timers[i].check_time_limit() {
+                self.record_run(i)?;
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+        }
+
+        self.sync_button_info()
+    }
+
+    fn sync_button_info(&mut self) -> Result<(), StateError> {
+        let now = self.clock.now();
+        let throttled = self.button_info_synced_at.is_some_and(|at| now - at < BUTTON_INFO_INTERVAL);
+        if !self.button_info_pending || throttled {
+            return Ok(());
+        }
+
+        self.button_info_pending = false;
+        self.button_info_synced_at = Some(now);
+        self.notify_listeners(&OutputEvent::SyncInfo(Box::new(self.info.clone())))
+    }
+
+    pub fn sync_all(&mut self) -> Result<(), StateError> {
         self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
-        self.notify_listeners(&OutputEvent::SyncSettings(self.settings.clone()))?;
-        self.notify_listeners(&OutputEvent::SyncInfo(self.info.clone()))?;
+        self.notify_listeners(&OutputEvent::SyncSettings(Box::new(self.settings.clone())))?;
+        self.notify_listeners(&OutputEvent::SyncInfo(Box::new(self.info.clone())))?;
+        self.notify_listeners(&OutputEvent::SyncLeaderboard(self.competition.leaderboard()))?;
+        self.notify_listeners(&OutputEvent::SyncHealth(self.health.clone()))?;
+
+        Ok(())
+    }
+
+    // Starts the race, or resets it when started again, at the time a button was pressed or right now
+    fn start_timers(&mut self, pressed_at: Option<Instant>) -> Result<(), StateError> {
+        let start_at = pressed_at.unwrap_or_else(|| self.clock.now());
+
+        if self.settings.lane_mode == LaneMode::Independent {
+            self.check_buttons()?;
+
+            // Sends every waiting lane on its way, lanes already out keep running
+            let mut started = false;
+            for timer in &mut self.timers {
+                started |= timer.start_at(start_at).is_ok();
+            }
+
+            if started {
+                self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+            }
+
+            return Ok(());
+        }
+
+        if self.get_timer_mut(0)?.get_state() == TimerState::Reset {
+            self.check_buttons()?;
+
+            for (i, timer) in self.timers.iter_mut().enumerate() {
+                if let Err(msg) = timer.start_at(start_at) {
+                    eprintln!("Timer {} couldn't be started: {}", i, msg);
+                }
+                self.started_at = self.clock.now();
+            }
+
+            self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+        } else {
+            if self.clock.now() - self.started_at <= MIN_RUN_BEFORE_RESET {
+                return Ok(());
+            }
+
+            self.reset_timers()?;
+            self.reset_at = self.clock.now();
+            self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+        }
+
+        Ok(())
+    }
+
+    // Stop button of a lane, disqualifies the lane during the countdown
+    fn press_lane_button(&mut self, lane: usize, pressed_at: Option<Instant>) -> Result<(), StateError> {
+        if self.false_start(lane)? || self.stop_timer(lane, pressed_at)?.is_ok() {
+            self.notify_listeners(&OutputEvent::SyncTimers(self.timers.clone()))?;
+        }
+
+        Ok(())
+    }
+
+    // Starting without buttons leaves nothing to stop the lanes but the web client
+    fn check_buttons(&self) -> Result<(), StateError> {
+        if self.info.buttons.connected {
+            return Ok(());
+        }
+
+        if self.settings.require_buttons {
+            return Err(StateError::Conflict("No button source is connected".to_string()));
+        }
+
+        eprintln!("Starting while no button source is connected");
+        Ok(())
+    }
+
+    fn require_independent_lanes(&self) -> Result<(), StateError> {
+        match self.settings.lane_mode {
+            LaneMode::Independent => Ok(()),
+            LaneMode::Synchronized => Err(StateError::Conflict("Lanes only start and reset together in a synchronized race".to_string())),
+        }
+    }
+
+    // Outer error is an invalid index, inner one means the timer wasn't running
+    // Stops the timer when a button was pressed, or right now
+    fn stop_timer(&mut self, id: usize, pressed_at: Option<Instant>) -> Result<Result<(), String>, StateError> {
+        let timer = self.get_timer_mut(id)?;
+        let stopped = match pressed_at {
+            Some(at) => timer.stop_at(at),
+            None => timer.stop(),
+        };
+
+        if let Err(msg) = stopped {
+            return Ok(Err(msg));
+        }
+
+        self.record_run(id)?;
+        Ok(Ok(()))
+    }
+
+    fn record_run(&mut self, id: usize) -> Result<(), StateError> {
+        let timer = self.get_timer_mut(id)?.clone();
+        let mut history = self.history.lock().unwrap();
+        history.record(id, &timer);
+        if let Err(e) = history.save() {
+            eprintln!("Couldn't save run history: {}", e);
+        }
+        drop(history);
+
+        if let (true, Some(athlete)) = (self.competition.is_active(), timer.athlete.clone()) {
+            self.competition.record(athlete, timer.as_millis(), timer.is_dnf());
+            self.save_competition();
+            self.notify_listeners(&OutputEvent::SyncLeaderboard(self.competition.leaderboard()))?;
+        }
+
+        Ok(())
+    }
+
+    // Returns whether the lane was disqualified for leaving during the countdown
+    fn false_start(&mut self, id: usize) -> Result<bool, StateError> {
+        if !self.settings.false_start_detection {
+            return Ok(false);
+        }
+
+        Ok(self.get_timer_mut(id)?.mark_false_start().is_ok())
+    }
+
+    // Resets every lane, moving the competition to its next heat if the current one was run
+    fn reset_timers(&mut self) -> Result<(), StateError> {
+        let heat_ran = self.timers.iter().any(|timer| timer.get_state() != TimerState::Reset);
+
+        for timer in &mut self.timers {
+            timer.reset();
+        }
+
+        if heat_ran && self.competition.is_active() {
+            self.competition.advance();
+            self.assign_heat();
+            self.save_competition();
+            self.notify_listeners(&OutputEvent::SyncLeaderboard(self.competition.leaderboard()))?;
+        }
 
         Ok(())
     }
 
-    fn get_timer_mut(&mut self, id: usize) -> Result<&mut Timer, String> {
+    fn assign_heat(&mut self) {
+        let lanes = self.competition
+            .current_heat()
+            .map(|heat| heat.lanes.clone())
+            .unwrap_or_default();
+        let roster = self.roster.lock().unwrap();
+
+        for (i, timer) in self.timers.iter_mut().enumerate() {
+            timer.athlete = lanes.get(i).copied().flatten().and_then(|id| {
+                let athlete = roster.get(id).cloned();
+                if athlete.is_none() {
+                    // Removed from the roster after the competition started
+                    eprintln!("Athlete {} of lane {} doesn't exist anymore", id, i);
+                }
+                athlete
+            });
+        }
+    }
+
+    fn save_settings(&self) {
+        if let Err(e) = self.settings.save() {
+            eprintln!("Couldn't save settings: {}", e);
+        }
+    }
+
+    fn save_competition(&self) {
+        if let Err(e) = self.competition.save() {
+            eprintln!("Couldn't save competition: {}", e);
+        }
+    }
+
+    // Recreates timers after a settings change, keeping athletes assigned to lanes that still exist
+    fn rebuild_timers(&mut self) {
+        let mut timers = create_timers(&self.settings, &self.clock);
+        for (timer, old) in timers.iter_mut().zip(self.timers.iter()) {
+            timer.athlete = old.athlete.clone();
+        }
+
+        self.timers = timers;
+    }
+
+    fn get_timer_mut(&mut self, id: usize) -> Result<&mut Timer, StateError> {
         match self.timers.get_mut(id) {
             Some(timer) => Ok(timer),
-            None => Err(format!("Timer index {} is out of bounds", id)),
+            None => Err(StateError::NotFound(format!("Timer index {} is out of bounds", id))),
         }
     }
 
-    fn notify_listeners(&mut self, event: &OutputEvent) -> Result<(), String> {
+    fn notify_listeners(&mut self, event: &OutputEvent) -> Result<(), StateError> {
         let errors = self.listeners
             .iter()
             .map(|listener| listener.send(event.clone()))
@@ -194,3 +809,489 @@ impl StateManager {
         Ok(())
     }
 }
+
+fn create_timers(settings: &Settings, clock: &Arc<dyn Clock>) -> Vec<Timer> {
+    (0..settings.lanes).map(|lane| Timer::new(clock.clone(), settings.countdown_for(lane), settings.time_limit)).collect()
+}
+
+// Maps a button to a lane, or clears the lane with None. A button only ever drives one lane
+fn set_lane_button(buttons: &mut Vec<Option<u8>>, lane: usize, button_id: Option<u8>) {
+    if buttons.len() <= lane {
+        buttons.resize(lane + 1, None);
+    }
+
+    if button_id.is_some() {
+        for button in buttons.iter_mut().filter(|button| **button == button_id) {
+            *button = None;
+        }
+    }
+    buttons[lane] = button_id;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::info::ButtonStatus;
+
+    fn state_manager(clock: &Arc<ManualClock>) -> (StateManager, mpsc::Receiver<OutputEvent>) {
+        let info = Info { ips: vec![], number_displays: 1, config: Config::default(), buttons: ButtonStatus::default() };
+        let mut state_manager = StateManager::with_clock(
+            Settings::default(),
+            info,
+            Arc::new(Mutex::new(RunHistory::default())),
+            Arc::new(Mutex::new(Roster::default())),
+            Competition::default(),
+            clock.clone(),
+        );
+
+        let (tx, rx) = mpsc::channel();
+        state_manager.add_listener(tx);
+
+        (state_manager, rx)
+    }
+
+    fn states(state_manager: &StateManager) -> Vec<TimerState> {
+        state_manager.timers.iter().map(Timer::get_state).collect()
+    }
+
+    fn last_timers(rx: &mpsc::Receiver<OutputEvent>) -> Option<Vec<Timer>> {
+        rx.try_iter()
+            .filter_map(|event| match event {
+                OutputEvent::SyncTimers(timers) => Some(timers),
+                _ => None,
+            })
+            .last()
+    }
+
+    #[test]
+    fn start_is_debounced_after_reset() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, rx) = state_manager(&clock);
+
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Reset, TimerState::Reset]);
+        assert!(last_timers(&rx).is_none());
+
+        clock.advance(Duration::from_millis(1100));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::CountingDown, TimerState::CountingDown]);
+        assert!(last_timers(&rx).is_some());
+    }
+
+    #[test]
+    fn countdown_leads_into_running() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        clock.advance(Duration::from_secs(2));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+
+        clock.advance(Duration::from_millis(2999));
+        assert_eq!(states(&state_manager), vec![TimerState::CountingDown, TimerState::CountingDown]);
+
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(states(&state_manager), vec![TimerState::Running, TimerState::Running]);
+    }
+
+    #[test]
+    fn stop_ends_a_single_lane_and_records_the_run() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, rx) = state_manager(&clock);
+        clock.advance(Duration::from_secs(2));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+
+        let result = state_manager.process(InputEvent::StopTimer(0));
+        assert!(matches!(result, Err(StateError::Conflict(_))));
+        assert_eq!(states(&state_manager)[0], TimerState::CountingDown);
+
+        clock.advance(Duration::from_millis(3000 + 4321));
+        state_manager.process(InputEvent::StopTimer(0)).unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        let timers = last_timers(&rx).unwrap();
+        assert_eq!(timers[0].get_state(), TimerState::Stopped);
+        assert_eq!(timers[0].as_millis(), 4321);
+        assert_eq!(timers[1].get_state(), TimerState::Running);
+
+        let history = state_manager.history.lock().unwrap();
+        assert_eq!(history.runs().len(), 1);
+        assert_eq!(history.runs()[0].lane, 0);
+        assert_eq!(history.runs()[0].elapsed_millis, 4321);
+    }
+
+    #[test]
+    fn lane_button_stops_its_lane() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        clock.advance(Duration::from_secs(2));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        clock.advance(Duration::from_secs(5));
+
+        state_manager.process(InputEvent::SetButtonState(2, true, None)).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Running, TimerState::Stopped]);
+
+        state_manager.process(InputEvent::SetButtonState(1, false, None)).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Running, TimerState::Stopped]);
+    }
+
+    #[test]
+    fn unassigned_lanes_have_no_button() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        set_lane_button(&mut state_manager.settings.split_buttons, 1, Some(7));
+        assert_eq!(state_manager.settings.split_buttons, vec![None, Some(7)]);
+        clock.advance(Duration::from_secs(2));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        clock.advance(Duration::from_secs(5));
+
+        // io_bank_num 0 is what unparsable daemon events are reported as
+        state_manager.process(InputEvent::SetButtonState(0, true, None)).unwrap();
+        assert_eq!(state_manager.settings.split_lane_for_button(0), None);
+        assert_eq!(states(&state_manager), vec![TimerState::Running, TimerState::Running]);
+    }
+
+    #[test]
+    fn start_and_reset_buttons_drive_every_lane() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        state_manager.process(InputEvent::SetRoleButtons(ButtonRole::Start, vec![5])).unwrap();
+        state_manager.process(InputEvent::SetRoleButtons(ButtonRole::Reset, vec![6])).unwrap();
+        clock.advance(Duration::from_secs(2));
+
+        state_manager.process(InputEvent::SetButtonState(5, false, None)).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Reset, TimerState::Reset]);
+
+        state_manager.process(InputEvent::SetButtonState(5, true, None)).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::CountingDown, TimerState::CountingDown]);
+
+        state_manager.process(InputEvent::SetButtonState(6, true, None)).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Reset, TimerState::Reset]);
+    }
+
+    #[test]
+    fn lane_button_uses_the_hardware_press_time() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        clock.advance(Duration::from_secs(2));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        clock.advance(Duration::from_millis(3000 + 5000));
+        let pressed_at = clock.now();
+
+        clock.advance(Duration::from_millis(40));
+        state_manager.process(InputEvent::SetButtonState(1, true, Some(pressed_at))).unwrap();
+        assert_eq!(state_manager.timers[0].as_millis(), 5000);
+    }
+
+    #[test]
+    fn start_button_uses_the_hardware_press_time() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        state_manager.settings.start_buttons = vec![5];
+        clock.advance(Duration::from_secs(2));
+        let pressed_at = clock.now();
+
+        clock.advance(Duration::from_millis(40));
+        state_manager.process(InputEvent::SetButtonState(5, true, Some(pressed_at))).unwrap();
+        clock.advance(Duration::from_millis(3000 + 5000 - 40));
+        state_manager.process(InputEvent::StopTimer(0)).unwrap();
+        assert_eq!(state_manager.timers[0].as_millis(), 5000);
+    }
+
+    #[test]
+    fn websocket_button_events_have_no_press_time() {
+        let event: InputEvent = serde_json::from_str(r#"{"SetButtonState": [2, true]}"#).unwrap();
+        assert!(matches!(event, InputEvent::SetButtonState(2, true, None)));
+    }
+
+    #[test]
+    #[cfg(feature = "display")]
+    fn bound_keys_start_and_stop_lanes() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        let binding = |key: &str, action| KeyBinding { key: key.to_string(), action };
+        state_manager.process(InputEvent::SetKeyBindings(vec![
+            binding("Space", KeyAction::Start),
+            binding("PageDown", KeyAction::StopLane(1)),
+        ])).unwrap();
+        clock.advance(Duration::from_secs(2));
+
+        state_manager.process(InputEvent::PressKey("Return".to_string(), None)).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Reset, TimerState::Reset]);
+
+        state_manager.process(InputEvent::PressKey("space".to_string(), None)).unwrap();
+        clock.advance(Duration::from_secs(5));
+        let pressed_at = clock.now();
+        clock.advance(Duration::from_millis(30));
+        state_manager.process(InputEvent::PressKey("PageDown".to_string(), Some(pressed_at))).unwrap();
+
+        assert_eq!(states(&state_manager), vec![TimerState::Running, TimerState::Stopped]);
+        assert_eq!(state_manager.timers[1].as_millis(), 2000);
+    }
+
+    #[test]
+    fn start_can_require_buttons() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        state_manager.settings.require_buttons = true;
+        clock.advance(Duration::from_millis(1100));
+
+        assert!(matches!(state_manager.process(InputEvent::StartTimers), Err(StateError::Conflict(_))));
+        assert_eq!(states(&state_manager), vec![TimerState::Reset, TimerState::Reset]);
+
+        state_manager.process(InputEvent::SetButtonsConnected(true)).unwrap();
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::CountingDown, TimerState::CountingDown]);
+    }
+
+    #[test]
+    fn hardware_presses_update_the_last_event() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+
+        state_manager.process(InputEvent::SetButtonState(7, true, None)).unwrap();
+        assert!(state_manager.info.buttons.last_event_at.is_none());
+
+        state_manager.process(InputEvent::SetButtonState(7, true, Some(clock.now()))).unwrap();
+        assert_eq!(state_manager.info.buttons.last_event_at, Some(clock.utc_now()));
+    }
+
+    #[test]
+    fn button_edges_sync_the_info_at_most_once_a_second() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, rx) = state_manager(&clock);
+        let info_events = |rx: &mpsc::Receiver<OutputEvent>| rx.try_iter().filter(|event| matches!(event, OutputEvent::SyncInfo(_))).count();
+
+        for _ in 0..10 {
+            state_manager.process(InputEvent::SetButtonState(7, true, Some(clock.now()))).unwrap();
+            state_manager.process(InputEvent::SetButtonState(7, false, Some(clock.now()))).unwrap();
+            clock.advance(Duration::from_millis(50));
+        }
+        state_manager.update().unwrap();
+        assert_eq!(info_events(&rx), 1);
+
+        // the last edge still gets through once the interval passed
+        clock.advance(Duration::from_secs(1));
+        state_manager.update().unwrap();
+        assert_eq!(info_events(&rx), 1);
+        state_manager.update().unwrap();
+        assert_eq!(info_events(&rx), 0);
+    }
+
+    #[test]
+    fn faults_are_kept_until_cleared() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, rx) = state_manager(&clock);
+
+        state_manager.process(InputEvent::ReportFault(FaultKind::Overcurrent, 3)).unwrap();
+        state_manager.process(InputEvent::ReportFault(FaultKind::CriticalError, 0)).unwrap();
+        let health = rx.try_iter()
+            .filter_map(|event| match event {
+                OutputEvent::SyncHealth(health) => Some(health),
+                _ => None,
+            })
+            .last()
+            .unwrap();
+        assert_eq!(health.fault_count, 2);
+        assert_eq!(health.faults[0].kind, FaultKind::Overcurrent);
+        assert_eq!(health.faults[0].io_bank_num, 3);
+
+        state_manager.process(InputEvent::ClearFaults).unwrap();
+        assert!(state_manager.health.faults.is_empty());
+    }
+
+    #[test]
+    fn stop_out_of_bounds_is_an_error() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+
+        assert!(matches!(state_manager.process(InputEvent::StopTimer(5)), Err(StateError::NotFound(_))));
+    }
+
+    #[test]
+    fn handle_replies_with_the_result() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        let (reply, result) = mpsc::channel();
+
+        state_manager.handle(Request { event: InputEvent::StopTimer(5), reply: Some(reply.clone()) });
+        assert!(matches!(result.recv().unwrap(), Err(StateError::NotFound(_))));
+
+        clock.advance(Duration::from_secs(2));
+        state_manager.handle(Request { event: InputEvent::StartTimers, reply: Some(reply) });
+        let timers = result.recv().unwrap().unwrap();
+        assert!(timers.iter().all(|timer| timer.get_state() == TimerState::CountingDown));
+    }
+
+    #[test]
+    fn start_button_resets_once_debounce_passed() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        clock.advance(Duration::from_secs(2));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+
+        clock.advance(Duration::from_millis(500));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::CountingDown, TimerState::CountingDown]);
+
+        clock.advance(Duration::from_millis(600));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Reset, TimerState::Reset]);
+
+        // a bounce right after the reset mustn't start a new run
+        clock.advance(Duration::from_millis(500));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Reset, TimerState::Reset]);
+    }
+
+    #[test]
+    fn start_button_bounce_doesnt_reset() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        state_manager.settings.start_buttons = vec![5];
+
+        state_manager.process(InputEvent::SetButtonState(5, true, Some(clock.now()))).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::CountingDown, TimerState::CountingDown]);
+
+        clock.advance(Duration::from_millis(100));
+        state_manager.process(InputEvent::SetButtonState(5, true, Some(clock.now()))).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::CountingDown, TimerState::CountingDown]);
+
+        clock.advance(Duration::from_secs(1));
+        state_manager.process(InputEvent::SetButtonState(5, true, Some(clock.now()))).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Reset, TimerState::Reset]);
+    }
+
+    #[test]
+    fn reset_clears_every_lane() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, rx) = state_manager(&clock);
+        clock.advance(Duration::from_secs(2));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        clock.advance(Duration::from_secs(5));
+        state_manager.process(InputEvent::StopTimer(1)).unwrap();
+
+        state_manager.process(InputEvent::ResetTimers).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Reset, TimerState::Reset]);
+        assert!(last_timers(&rx).unwrap().iter().all(|timer| timer.as_millis() == 0));
+    }
+
+    #[test]
+    fn independent_lanes_start_and_reset_on_their_own() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        assert!(matches!(state_manager.process(InputEvent::StartTimer(0)), Err(StateError::Conflict(_))));
+
+        state_manager.process(InputEvent::SetLaneMode(LaneMode::Independent)).unwrap();
+        state_manager.process(InputEvent::StartTimer(0)).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::CountingDown, TimerState::Reset]);
+
+        clock.advance(Duration::from_secs(5));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Running, TimerState::CountingDown]);
+
+        state_manager.process(InputEvent::ResetTimer(0)).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Reset, TimerState::CountingDown]);
+    }
+
+    #[test]
+    fn lanes_can_have_their_own_countdown() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        state_manager.process(InputEvent::SetLaneCountdown(1, Some(10))).unwrap();
+        clock.advance(Duration::from_secs(2));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(states(&state_manager), vec![TimerState::Running, TimerState::CountingDown]);
+    }
+
+    #[test]
+    fn update_marks_lanes_over_the_time_limit() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, rx) = state_manager(&clock);
+        state_manager.settings.time_limit = Some(60);
+        state_manager.rebuild_timers();
+        clock.advance(Duration::from_secs(2));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        clock.advance(Duration::from_secs(3 + 30));
+        state_manager.process(InputEvent::StopTimer(0)).unwrap();
+
+        clock.advance(Duration::from_secs(31));
+        state_manager.update().unwrap();
+
+        let timers = last_timers(&rx).unwrap();
+        assert_eq!(timers[0].get_state(), TimerState::Stopped);
+        assert_eq!(timers[1].get_state(), TimerState::Dnf);
+        assert_eq!(timers[1].as_millis(), 60_000);
+        assert!(state_manager.history.lock().unwrap().runs()[1].dnf);
+    }
+
+    #[test]
+    fn competitions_need_known_athletes_and_lanes() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        let id = state_manager.roster.lock().unwrap().add("Ada".to_string(), 1, "Open".to_string()).id;
+
+        let heat = |lanes| Heat { round: 1, lanes };
+        assert!(matches!(
+            state_manager.process(InputEvent::StartCompetition(vec![heat(vec![Some(id), Some(id + 1)])])),
+            Err(StateError::NotFound(_))
+        ));
+        assert!(matches!(
+            state_manager.process(InputEvent::StartCompetition(vec![heat(vec![Some(id), None, None])])),
+            Err(StateError::Invalid(_))
+        ));
+        assert!(!state_manager.competition.is_active());
+
+        state_manager.process(InputEvent::StartCompetition(vec![heat(vec![None, Some(id)])])).unwrap();
+        assert_eq!(state_manager.timers[1].athlete.as_ref().unwrap().id, id);
+    }
+
+    #[test]
+    fn lane_buttons_move_between_lanes_and_can_be_cleared() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+
+        state_manager.process(InputEvent::SetLaneButton(1, Some(1))).unwrap();
+        assert_eq!(&state_manager.settings.lane_buttons[..2], &[None, Some(1)]);
+
+        state_manager.process(InputEvent::SetLaneButton(1, None)).unwrap();
+        assert_eq!(&state_manager.settings.lane_buttons[..2], &[None, None]);
+
+        state_manager.process(InputEvent::SetSplitButton(3, Some(7))).unwrap();
+        assert_eq!(state_manager.settings.split_buttons, vec![None, None, None, Some(7)]);
+    }
+
+    #[test]
+    fn time_limit_applies_to_running_lanes() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, rx) = state_manager(&clock);
+        clock.advance(Duration::from_secs(2));
+        state_manager.process(InputEvent::StartTimers).unwrap();
+        clock.advance(Duration::from_secs(3 + 30));
+
+        state_manager.process(InputEvent::SetTimeLimit(Some(20))).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::Running, TimerState::Running]);
+
+        state_manager.update().unwrap();
+        let timers = last_timers(&rx).unwrap();
+        assert_eq!(timers[0].get_state(), TimerState::Dnf);
+        assert_eq!(timers[0].as_millis(), 20_000);
+    }
+
+    #[test]
+    fn lane_countdown_leaves_the_other_lanes_running() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut state_manager, _rx) = state_manager(&clock);
+        state_manager.settings.lane_mode = LaneMode::Independent;
+        state_manager.process(InputEvent::StartTimer(0)).unwrap();
+
+        assert!(matches!(
+            state_manager.process(InputEvent::SetLaneCountdown(0, Some(10))),
+            Err(StateError::Conflict(_))
+        ));
+        state_manager.process(InputEvent::SetLaneCountdown(1, Some(10))).unwrap();
+        assert_eq!(states(&state_manager), vec![TimerState::CountingDown, TimerState::Reset]);
+        assert_eq!(state_manager.timers[1].countdown_duration, Duration::from_secs(10));
+    }
+}
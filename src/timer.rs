@@ -1,39 +1,65 @@
+use std::sync::Arc;
 use std::time::{Instant, Duration};
 
 use chrono::{DateTime, Utc};
+use serde::Serialize;
 
-#[derive(Debug, Clone, Copy)]
+use crate::athletes::Athlete;
+use crate::clock::Clock;
+
+#[derive(Debug, Clone)]
 pub struct Timer {
     started_at: Option<Instant>,
     pub started_at_datetime: Option<DateTime<Utc>>,
     stopped_at: Option<Instant>,
     pub stopped_at_datetime: Option<DateTime<Utc>>,
+    splits: Vec<Instant>,
+    false_start: bool,
+    dnf: bool,
     pub countdown_duration: Duration,
+    pub time_limit: Option<Duration>,
+    pub athlete: Option<Athlete>,
+    clock: Arc<dyn Clock>,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Default, Serialize)]
 pub enum TimerState {
+    #[default]
     Reset,
     CountingDown,
     Running,
     Stopped,
+    FalseStart,
+    Dnf,
 }
 
 impl Timer {
-    pub fn new(countdown_seconds: u64) -> Self {
+    pub fn new(clock: Arc<dyn Clock>, countdown_seconds: u64, time_limit_seconds: Option<u64>) -> Self {
         Self {
             started_at: None,
             started_at_datetime: None,
             stopped_at: None,
             stopped_at_datetime: None,
+            splits: vec![],
+            false_start: false,
+            dnf: false,
             countdown_duration: Duration::from_secs(countdown_seconds),
+            time_limit: time_limit_seconds.map(Duration::from_secs),
+            athlete: None,
+            clock,
         }
     }
 
     pub fn start(&mut self) -> Result<(), String> {
+        self.start_at(self.clock.now())
+    }
+
+    // Starts the timer at the moment a start button was pressed, which may lie a little in the past
+    pub fn start_at(&mut self, at: Instant) -> Result<(), String> {
         if self.get_state() == TimerState::Reset {
-            self.started_at = Some(Instant::now());
-            self.started_at_datetime = Some(Utc::now());
+            let at = at.min(self.clock.now());
+            self.started_at = Some(at);
+            self.started_at_datetime = Some(self.datetime_of(at));
             Ok(())
         } else {
             Err("Timer hasn't been reset".to_string())
@@ -41,19 +67,100 @@ impl Timer {
     }
 
     pub fn stop(&mut self) -> Result<(), String> {
-        if self.get_state() == TimerState::Running {
-            self.stopped_at = Some(Instant::now());
-            self.stopped_at_datetime = Some(Utc::now());
+        self.stop_at(self.clock.now())
+    }
+
+    // Stops the timer at the moment a button was pressed, which may lie a little in the past
+    pub fn stop_at(&mut self, at: Instant) -> Result<(), String> {
+        if self.get_state() != TimerState::Running {
+            return Err("Timer isn't running".to_string());
+        }
+
+        let at = self.checked_press(at)?;
+        self.stopped_at = Some(at);
+        self.stopped_at_datetime = Some(self.datetime_of(at));
+        Ok(())
+    }
+
+    // Freezes a lane that left before the end of the countdown
+    pub fn mark_false_start(&mut self) -> Result<(), String> {
+        if self.get_state() == TimerState::CountingDown {
+            self.stopped_at = Some(self.clock.now());
+            self.stopped_at_datetime = Some(self.clock.utc_now());
+            self.false_start = true;
             Ok(())
         } else {
-            Err("Timer isn't running".to_string())
+            Err("Timer isn't counting down".to_string())
+        }
+    }
+
+    // Stops the timer at exactly the time limit once it has been exceeded, returns whether it did
+    pub fn check_time_limit(&mut self) -> bool {
+        let (limit, started_at, started_at_datetime) = match (self.time_limit, self.started_at, self.started_at_datetime) {
+            (Some(limit), Some(start), Some(start_datetime)) => (limit, start, start_datetime),
+            _ => return false,
+        };
+
+        if self.get_state() != TimerState::Running || self.as_millis() < limit.as_millis() as i128 {
+            return false;
+        }
+
+        let elapsed = self.countdown_duration + limit;
+        self.stopped_at = Some(started_at + elapsed);
+        self.stopped_at_datetime = chrono::Duration::from_std(elapsed).ok().map(|x| started_at_datetime + x);
+        self.dnf = true;
+        true
+    }
+
+    pub fn is_dnf(&self) -> bool {
+        self.dnf
+    }
+
+    pub fn split(&mut self) -> Result<(), String> {
+        self.split_at(self.clock.now())
+    }
+
+    pub fn split_at(&mut self, at: Instant) -> Result<(), String> {
+        if self.get_state() != TimerState::Running {
+            return Err("Timer isn't running".to_string());
+        }
+
+        let at = self.checked_press(at)?;
+        self.splits.push(at);
+        Ok(())
+    }
+
+    // A press can't be in the future, and one from before the end of the countdown doesn't belong to this run
+    fn checked_press(&self, at: Instant) -> Result<Instant, String> {
+        let at = at.min(self.clock.now());
+        match self.started_at {
+            Some(start) if at >= start + self.countdown_duration => Ok(at),
+            _ => Err("Button was pressed before the run started".to_string()),
         }
     }
 
+    fn datetime_of(&self, at: Instant) -> DateTime<Utc> {
+        let ago = self.clock.now().saturating_duration_since(at);
+        self.clock.utc_now() - chrono::Duration::from_std(ago).unwrap_or_else(|_| chrono::Duration::zero())
+    }
+
+    // Race time at each checkpoint, in the order they were reached
+    pub fn split_millis(&self) -> Vec<i128> {
+        let start = match self.started_at {
+            Some(start) => start,
+            None => return vec![],
+        };
+
+        self.splits
+            .iter()
+            .map(|split| (*split - start).as_millis() as i128 - self.countdown_duration.as_millis() as i128)
+            .collect()
+    }
+
     pub fn as_millis(&self) -> i128 {
         match self.stopped_at {
             Some(stop) => self.started_at.map_or(0, |start| (stop - start).as_millis() as i128 - self.countdown_duration.as_millis() as i128),
-            None => self.started_at.map_or(0, |start| (Instant::now() - start).as_millis() as i128 - self.countdown_duration.as_millis() as i128),
+            None => self.started_at.map_or(0, |start| (self.clock.now() - start).as_millis() as i128 - self.countdown_duration.as_millis() as i128),
         }
     }
 
@@ -62,16 +169,21 @@ impl Timer {
         self.started_at_datetime = None;
         self.stopped_at = None;
         self.stopped_at_datetime = None;
+        self.splits.clear();
+        self.false_start = false;
+        self.dnf = false;
     }
 
     pub fn get_state(&self) -> TimerState {
         match self.started_at {
             None => TimerState::Reset,
+            Some(_) if self.false_start => TimerState::FalseStart,
             Some(_) => {
                 if self.as_millis() < 0 {
                     TimerState::CountingDown
                 } else {
                     match self.stopped_at {
+                        Some(_) if self.dnf => TimerState::Dnf,
                         Some(_) => TimerState::Stopped,
                         None => TimerState::Running,
                     }
@@ -93,13 +205,154 @@ impl Timer {
 
                 format!("{}", seconds_left)
             },
-            _ => {
-                let millis = elapsed_millis % 1000;
-                let seconds = (elapsed_millis / 1000) % 60;
-                let minutes = elapsed_millis / (1000 * 60);
-
-                format!("{:02}:{:02}.{:03}", minutes, seconds, millis)
-            }
+            TimerState::FalseStart => "FALSE START".to_string(),
+            _ => format_millis(elapsed_millis),
         }
     }
 }
+
+pub fn format_millis(millis: i128) -> String {
+    let elapsed_millis = millis.abs();
+    let millis = elapsed_millis % 1000;
+    let seconds = (elapsed_millis / 1000) % 60;
+    let minutes = elapsed_millis / (1000 * 60);
+
+    format!("{:02}:{:02}.{:03}", minutes, seconds, millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn timer(clock: &Arc<ManualClock>, countdown: u64, time_limit: Option<u64>) -> Timer {
+        Timer::new(clock.clone(), countdown, time_limit)
+    }
+
+    #[test]
+    fn counts_down_before_running() {
+        let clock = Arc::new(ManualClock::new());
+        let mut timer = timer(&clock, 3, None);
+        assert_eq!(timer.get_state(), TimerState::Reset);
+
+        timer.start().unwrap();
+        assert_eq!(timer.get_state(), TimerState::CountingDown);
+        assert_eq!(timer.format(), "3");
+
+        clock.advance(Duration::from_millis(2500));
+        assert_eq!(timer.format(), "1");
+
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(timer.get_state(), TimerState::Running);
+        assert_eq!(timer.as_millis(), 0);
+    }
+
+    #[test]
+    fn stops_only_while_running() {
+        let clock = Arc::new(ManualClock::new());
+        let mut timer = timer(&clock, 3, None);
+        assert!(timer.stop().is_err());
+
+        timer.start().unwrap();
+        assert!(timer.stop().is_err());
+
+        clock.advance(Duration::from_millis(3000 + 61_234));
+        timer.stop().unwrap();
+        clock.advance(Duration::from_secs(10));
+
+        assert_eq!(timer.get_state(), TimerState::Stopped);
+        assert_eq!(timer.as_millis(), 61_234);
+        assert_eq!(timer.format(), "01:01.234");
+        assert!(timer.start().is_err());
+    }
+
+    #[test]
+    fn stops_at_the_press_time() {
+        let clock = Arc::new(ManualClock::new());
+        let mut timer = timer(&clock, 3, None);
+        timer.start().unwrap();
+        let during_countdown = clock.now() + Duration::from_secs(2);
+
+        clock.advance(Duration::from_secs(10));
+        assert!(timer.stop_at(during_countdown).is_err());
+
+        timer.stop_at(clock.now() - Duration::from_millis(250)).unwrap();
+        assert_eq!(timer.as_millis(), 6750);
+        assert_eq!(
+            timer.stopped_at_datetime.unwrap() - timer.started_at_datetime.unwrap(),
+            chrono::Duration::milliseconds(9750),
+        );
+    }
+
+    #[test]
+    fn reset_clears_the_run() {
+        let clock = Arc::new(ManualClock::new());
+        let mut timer = timer(&clock, 0, None);
+        timer.start().unwrap();
+        clock.advance(Duration::from_secs(1));
+        timer.split().unwrap();
+        timer.stop().unwrap();
+
+        timer.reset();
+        assert_eq!(timer.get_state(), TimerState::Reset);
+        assert_eq!(timer.as_millis(), 0);
+        assert!(timer.split_millis().is_empty());
+        assert!(timer.started_at_datetime.is_none());
+    }
+
+    #[test]
+    fn records_splits_relative_to_the_start() {
+        let clock = Arc::new(ManualClock::new());
+        let mut timer = timer(&clock, 3, None);
+        timer.start().unwrap();
+        assert!(timer.split().is_err());
+
+        clock.advance(Duration::from_millis(3000 + 1200));
+        timer.split().unwrap();
+        clock.advance(Duration::from_millis(800));
+        timer.split().unwrap();
+
+        assert_eq!(timer.split_millis(), vec![1200, 2000]);
+    }
+
+    #[test]
+    fn false_start_only_during_countdown() {
+        let clock = Arc::new(ManualClock::new());
+        let mut timer = timer(&clock, 3, None);
+        timer.start().unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        timer.mark_false_start().unwrap();
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(timer.get_state(), TimerState::FalseStart);
+        assert_eq!(timer.format(), "FALSE START");
+        assert!(timer.stop().is_err());
+
+        timer.reset();
+        timer.start().unwrap();
+        clock.advance(Duration::from_secs(4));
+        assert!(timer.mark_false_start().is_err());
+    }
+
+    #[test]
+    fn stops_at_the_time_limit() {
+        let clock = Arc::new(ManualClock::new());
+        let mut timer = timer(&clock, 3, Some(180));
+        timer.start().unwrap();
+
+        clock.advance(Duration::from_secs(3 + 179));
+        assert!(!timer.check_time_limit());
+        assert_eq!(timer.get_state(), TimerState::Running);
+
+        clock.advance(Duration::from_millis(1500));
+        assert!(timer.check_time_limit());
+        assert_eq!(timer.get_state(), TimerState::Dnf);
+        assert!(timer.is_dnf());
+        assert_eq!(timer.as_millis(), 180_000);
+        assert_eq!(
+            timer.stopped_at_datetime.unwrap() - timer.started_at_datetime.unwrap(),
+            chrono::Duration::seconds(183),
+        );
+        assert!(!timer.check_time_limit());
+    }
+}
@@ -1,7 +1,9 @@
 use std::thread::{spawn, JoinHandle};
-use std::sync::{mpsc, Mutex};
+use std::sync::{mpsc, Arc, Mutex};
+use std::fmt;
 use std::fs::remove_file;
-use std::io::Write;
+use std::io::{ErrorKind, Write};
+use std::time::Duration;
 
 use serde_json::json;
 use serde::Deserialize;
@@ -10,76 +12,178 @@ use actix_web::{
     rt,
     get,
     post,
+    delete,
     web,
-    http::header,
+    http::{header, StatusCode},
     App,
+    HttpRequest,
     HttpResponse,
     HttpServer,
     Responder,
+    ResponseError,
     Error,
 };
 use actix_files as fs;
 use actix_multipart::Multipart;
 
-use futures_util::TryStreamExt as _;
+use actix_web::dev::{Service as _, ServiceResponse};
+use futures_util::{future::{ready, Either}, StreamExt as _, TryFutureExt as _, TryStreamExt as _};
+use actix_ws::Message;
 
-use chrono::Utc;
-
-use crate::state::{InputEvent, OutputEvent};
+use crate::state::{InputEvent, OutputEvent, Request, StateError};
 use crate::broadcast::Broadcaster;
 use crate::assets::get_background_path;
+use crate::settings::{ButtonFilter, ButtonRole, KeyBinding, LaneMode, SerialSettings, Settings};
+use crate::runs::RunHistory;
+use crate::athletes::Roster;
+use crate::competition::Heat;
+use crate::config::Config;
+use crate::schema;
+use crate::timer::Timer;
+use crate::auth::{Auth, request_token, required_role, required_role_for_event};
+
+// How long a request waits for the state thread before giving up
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Errors are answered as {"error": "<message>"} with a matching status code
+#[derive(Debug)]
+enum ApiError {
+    BadRequest(String),
+    Unauthorized,
+    NotFound(String),
+    Conflict(String),
+    Unavailable(String),
+    Internal(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Unauthorized => write!(f, "Unauthorized"),
+            ApiError::BadRequest(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::Conflict(msg)
+            | ApiError::Unavailable(msg)
+            | ApiError::Internal(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({ "error": self.to_string() }))
+    }
+}
+
+impl From<StateError> for ApiError {
+    fn from(error: StateError) -> Self {
+        match error {
+            StateError::NotFound(msg) => ApiError::NotFound(msg),
+            StateError::Invalid(msg) => ApiError::BadRequest(msg),
+            StateError::Conflict(msg) => ApiError::Conflict(msg),
+        }
+    }
+}
 
 struct AppState {
-    sender: Mutex<mpsc::Sender<InputEvent>>,
+    sender: Mutex<mpsc::Sender<Request>>,
+    history: Arc<Mutex<RunHistory>>,
+    roster: Arc<Mutex<Roster>>,
+    config: Config,
+    auth: Arc<Mutex<Auth>>,
 }
 
 impl AppState {
-    fn send(&self, event: InputEvent) {
-        self.sender.lock().unwrap().send(event).unwrap();
+    // Hands the event to the state thread and waits for the timers as they are afterwards
+    async fn request(&self, event: InputEvent) -> Result<Vec<Timer>, ApiError> {
+        let (reply, result) = mpsc::channel();
+        self.sender
+            .lock()
+            .unwrap()
+            .send(Request { event, reply: Some(reply) })
+            .map_err(|_| ApiError::Unavailable("State thread isn't running".to_string()))?;
+
+        web::block(move || result.recv_timeout(REPLY_TIMEOUT))
+            .await
+            .map_err(|e| ApiError::Internal(e.to_string()))?
+            .map_err(|_| ApiError::Unavailable("State thread didn't reply".to_string()))?
+            .map_err(ApiError::from)
     }
 }
 
 #[post("/api/start_timer")]
-async fn start_timer(data: web::Data<AppState>) -> impl Responder {
-    data.send(InputEvent::StartTimers);
-    HttpResponse::Ok().body("OK")
+async fn start_timer(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::StartTimers).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("/api/start_timer/{timer_id}")]
+async fn start_lane(data: web::Data<AppState>, path: web::Path<usize>) -> Result<HttpResponse, ApiError> {
+    let timer_id = path.into_inner();
+    let timers = data.request(InputEvent::StartTimer(timer_id)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
 }
 
 #[post("/api/stop_timer/{timer_id}")]
-async fn stop_timer(data: web::Data<AppState>, path: web::Path<usize>) -> impl Responder {
+async fn stop_timer(data: web::Data<AppState>, path: web::Path<usize>) -> Result<HttpResponse, ApiError> {
     let timer_id = path.into_inner();
-    data.send(InputEvent::StopTimer(timer_id));
-    HttpResponse::Ok().body("OK")
+    let timers = data.request(InputEvent::StopTimer(timer_id)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("/api/split/{timer_id}")]
+async fn split(data: web::Data<AppState>, path: web::Path<usize>) -> Result<HttpResponse, ApiError> {
+    let timer_id = path.into_inner();
+    let timers = data.request(InputEvent::Split(timer_id)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
 }
 
 #[post("/api/stop_timers")]
-async fn stop_timers(data: web::Data<AppState>) -> impl Responder {
-    data.send(InputEvent::StopTimers);
-    HttpResponse::Ok().body("OK")
+async fn stop_timers(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::StopTimers).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
 }
 
 #[post("/api/reset_timer")]
-async fn reset_timer(data: web::Data<AppState>) -> impl Responder {
-    data.send(InputEvent::ResetTimers);
-    HttpResponse::Ok().body("OK")
+async fn reset_timer(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::ResetTimers).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("/api/reset_timer/{timer_id}")]
+async fn reset_lane(data: web::Data<AppState>, path: web::Path<usize>) -> Result<HttpResponse, ApiError> {
+    let timer_id = path.into_inner();
+    let timers = data.request(InputEvent::ResetTimer(timer_id)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
 }
 
 #[post("/api/request_sync")]
-async fn request_sync(data: web::Data<AppState>) -> impl Responder {
-    data.send(InputEvent::RequestSync);
-    HttpResponse::Ok().body("OK")
+async fn request_sync(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::RequestSync).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
 }
 
 #[post("api/enable_debug")]
-async fn enable_debug(data: web::Data<AppState>) -> impl Responder {
-    data.send(InputEvent::SetDebug(true));
-    HttpResponse::Ok().body("OK")
+async fn enable_debug(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetDebug(true)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
 }
 
 #[post("api/disable_debug")]
-async fn disable_debug(data: web::Data<AppState>) -> impl Responder {
-    data.send(InputEvent::SetDebug(false));
-    HttpResponse::Ok().body("OK")
+async fn disable_debug(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetDebug(false)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
 }
 
 #[derive(Deserialize)]
@@ -88,16 +192,195 @@ struct Countdown {
 }
 
 #[post("api/set_countdown")]
-async fn set_countdown(data: web::Data<AppState>, info: web::Json<Countdown>) -> impl Responder {
-    data.send(InputEvent::SetCountdown(info.countdown));
-    HttpResponse::Ok().body("OK")
+async fn set_countdown(data: web::Data<AppState>, info: web::Json<Countdown>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetCountdown(info.countdown)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[derive(Deserialize)]
+struct LaneCountdown {
+    lane: usize,
+    // None falls back to the common countdown
+    countdown: Option<u64>,
+}
+
+#[post("api/set_lane_countdown")]
+async fn set_lane_countdown(data: web::Data<AppState>, info: web::Json<LaneCountdown>) -> Result<HttpResponse, ApiError> {
+    if info.lane >= Settings::MAX_LANES {
+        return Err(ApiError::BadRequest(format!("lane must be lower than {}", Settings::MAX_LANES)));
+    }
+
+    let timers = data.request(InputEvent::SetLaneCountdown(info.lane, info.countdown)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LaneModeForm {
+    lane_mode: LaneMode,
+}
+
+#[post("api/set_lane_mode")]
+async fn set_lane_mode(data: web::Data<AppState>, info: web::Json<LaneModeForm>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetLaneMode(info.lane_mode)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[derive(Deserialize)]
+struct Lanes {
+    lanes: usize,
+}
+
+#[post("api/set_lanes")]
+async fn set_lanes(data: web::Data<AppState>, info: web::Json<Lanes>) -> Result<HttpResponse, ApiError> {
+    if info.lanes == 0 || info.lanes > Settings::MAX_LANES {
+        return Err(ApiError::BadRequest(format!("lanes must be between 1 and {}", Settings::MAX_LANES)));
+    }
+
+    let timers = data.request(InputEvent::SetLanes(info.lanes)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[derive(Deserialize)]
+struct LaneButton {
+    lane: usize,
+    // None clears the button of the lane
+    button: Option<u8>,
+}
+
+#[post("api/set_lane_button")]
+async fn set_lane_button(data: web::Data<AppState>, info: web::Json<LaneButton>) -> Result<HttpResponse, ApiError> {
+    if info.lane >= Settings::MAX_LANES {
+        return Err(ApiError::BadRequest(format!("lane must be lower than {}", Settings::MAX_LANES)));
+    }
+
+    let timers = data.request(InputEvent::SetLaneButton(info.lane, info.button)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("api/set_split_button")]
+async fn set_split_button(data: web::Data<AppState>, info: web::Json<LaneButton>) -> Result<HttpResponse, ApiError> {
+    if info.lane >= Settings::MAX_LANES {
+        return Err(ApiError::BadRequest(format!("lane must be lower than {}", Settings::MAX_LANES)));
+    }
+
+    let timers = data.request(InputEvent::SetSplitButton(info.lane, info.button)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("api/set_start_mat_button")]
+async fn set_start_mat_button(data: web::Data<AppState>, info: web::Json<LaneButton>) -> Result<HttpResponse, ApiError> {
+    if info.lane >= Settings::MAX_LANES {
+        return Err(ApiError::BadRequest(format!("lane must be lower than {}", Settings::MAX_LANES)));
+    }
+
+    let timers = data.request(InputEvent::SetStartMatButton(info.lane, info.button)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[derive(Deserialize)]
+struct RoleButtons {
+    role: ButtonRole,
+    buttons: Vec<u8>,
+}
+
+#[post("api/set_role_buttons")]
+async fn set_role_buttons(data: web::Data<AppState>, info: web::Json<RoleButtons>) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    let timers = data.request(InputEvent::SetRoleButtons(info.role, info.buttons)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyBindings {
+    key_bindings: Vec<KeyBinding>,
+}
+
+#[post("api/set_key_bindings")]
+async fn set_key_bindings(data: web::Data<AppState>, info: web::Json<KeyBindings>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetKeyBindings(info.into_inner().key_bindings)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ButtonFilters {
+    button_filters: Vec<ButtonFilter>,
+}
+
+#[post("api/set_button_filters")]
+async fn set_button_filters(data: web::Data<AppState>, info: web::Json<ButtonFilters>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetButtonFilters(info.into_inner().button_filters)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("api/set_serial")]
+async fn set_serial(data: web::Data<AppState>, info: web::Json<SerialSettings>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetSerial(info.into_inner())).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("api/enable_false_start")]
+async fn enable_false_start(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetFalseStartDetection(true)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("api/disable_false_start")]
+async fn disable_false_start(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetFalseStartDetection(false)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("api/enable_require_buttons")]
+async fn enable_require_buttons(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetRequireButtons(true)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("api/disable_require_buttons")]
+async fn disable_require_buttons(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetRequireButtons(false)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TimeLimit {
+    time_limit: Option<u64>,
+}
+
+#[post("api/set_time_limit")]
+async fn set_time_limit(data: web::Data<AppState>, info: web::Json<TimeLimit>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::SetTimeLimit(info.time_limit)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Pins {
+    operator_pin: Option<String>,
+    admin_pin: Option<String>,
+}
+
+#[post("api/set_pins")]
+async fn set_pins(data: web::Data<AppState>, info: web::Json<Pins>) -> Result<HttpResponse, ApiError> {
+    let info = info.into_inner();
+    let timers = data.request(InputEvent::SetPins(info.operator_pin, info.admin_pin)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
 }
 
 #[post("api/delete_background")]
-async fn delete_background(data: web::Data<AppState>) -> impl Responder {
-    remove_file(get_background_path()).unwrap();
-    data.send(InputEvent::ReloadBackground);
-    HttpResponse::Ok().body("OK")
+async fn delete_background(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    match remove_file(get_background_path()) {
+        Ok(()) => (),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(ApiError::NotFound("No background has been uploaded".to_string())),
+        Err(e) => return Err(ApiError::Internal(e.to_string())),
+    }
+
+    let timers = data.request(InputEvent::ReloadBackground).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
 }
 
 #[post("api/upload_background")]
@@ -118,14 +401,158 @@ async fn upload_background(data: web::Data<AppState>, mut payload: Multipart) ->
     }
 
     println!("file uploaded");
-    data.send(InputEvent::ReloadBackground);
-    Ok(HttpResponse::Ok().body("OK").into())
+    let timers = data.request(InputEvent::ReloadBackground).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
 }
 
 #[post("api/toggle_display")]
-async fn toggle_display(data: web::Data<AppState>) -> impl Responder {
-    data.send(InputEvent::ToggleDisplay);
-    HttpResponse::Ok().body("OK")
+async fn toggle_display(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::ToggleDisplay).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[get("/api/runs")]
+async fn list_runs(data: web::Data<AppState>) -> impl Responder {
+    let history = data.history.lock().unwrap();
+    HttpResponse::Ok().json(json!({ "runs": history.runs() }))
+}
+
+#[get("/api/runs/export")]
+async fn export_runs(data: web::Data<AppState>) -> impl Responder {
+    let csv = data.history.lock().unwrap().to_csv();
+
+    HttpResponse::Ok()
+        .append_header((header::CONTENT_TYPE, "text/csv"))
+        .append_header((header::CONTENT_DISPOSITION, "attachment; filename=\"runs.csv\""))
+        .body(csv)
+}
+
+#[delete("/api/runs/{run_id}")]
+async fn delete_run(data: web::Data<AppState>, path: web::Path<u64>) -> Result<HttpResponse, ApiError> {
+    let run_id = path.into_inner();
+    let mut history = data.history.lock().unwrap();
+
+    if history.delete(run_id).is_none() {
+        return Err(ApiError::NotFound(format!("Run {} not found", run_id)));
+    }
+
+    history.save().map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[delete("/api/runs")]
+async fn clear_runs(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let mut history = data.history.lock().unwrap();
+    history.clear();
+
+    history.save().map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[derive(Deserialize)]
+struct AthleteForm {
+    name: String,
+    bib: u32,
+    #[serde(default)]
+    category: String,
+}
+
+#[get("/api/athletes")]
+async fn list_athletes(data: web::Data<AppState>) -> impl Responder {
+    let roster = data.roster.lock().unwrap();
+    HttpResponse::Ok().json(json!({ "athletes": roster.athletes() }))
+}
+
+#[post("/api/athletes")]
+async fn add_athlete(data: web::Data<AppState>, form: web::Json<AthleteForm>) -> Result<HttpResponse, ApiError> {
+    let form = form.into_inner();
+    let mut roster = data.roster.lock().unwrap();
+    let athlete = roster.add(form.name, form.bib, form.category).clone();
+
+    roster.save().map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(athlete))
+}
+
+#[post("/api/athletes/{athlete_id}")]
+async fn update_athlete(data: web::Data<AppState>, path: web::Path<u64>, form: web::Json<AthleteForm>) -> Result<HttpResponse, ApiError> {
+    let athlete_id = path.into_inner();
+    let form = form.into_inner();
+    let mut roster = data.roster.lock().unwrap();
+
+    let athlete = match roster.update(athlete_id, form.name, form.bib, form.category) {
+        Some(athlete) => athlete.clone(),
+        None => return Err(ApiError::NotFound(format!("Athlete {} not found", athlete_id))),
+    };
+
+    roster.save().map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(athlete))
+}
+
+#[delete("/api/athletes/{athlete_id}")]
+async fn delete_athlete(data: web::Data<AppState>, path: web::Path<u64>) -> Result<HttpResponse, ApiError> {
+    let athlete_id = path.into_inner();
+    let mut roster = data.roster.lock().unwrap();
+
+    if roster.remove(athlete_id).is_none() {
+        return Err(ApiError::NotFound(format!("Athlete {} not found", athlete_id)));
+    }
+
+    roster.save().map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AssignAthlete {
+    lane: usize,
+    athlete_id: Option<u64>,
+}
+
+#[post("/api/assign_athlete")]
+async fn assign_athlete(data: web::Data<AppState>, info: web::Json<AssignAthlete>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::AssignAthlete(info.lane, info.athlete_id)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[derive(Deserialize)]
+struct StartList {
+    heats: Vec<Heat>,
+}
+
+#[post("/api/competition")]
+async fn start_competition(data: web::Data<AppState>, info: web::Json<StartList>) -> Result<HttpResponse, ApiError> {
+    if info.heats.is_empty() {
+        return Err(ApiError::BadRequest("Start list must contain at least one heat".to_string()));
+    }
+
+    let timers = data.request(InputEvent::StartCompetition(info.into_inner().heats)).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[delete("/api/competition")]
+async fn end_competition(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::EndCompetition).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("api/toggle_leaderboard")]
+async fn toggle_leaderboard(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::ToggleLeaderboard).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[post("/api/clear_faults")]
+async fn clear_faults(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let timers = data.request(InputEvent::ClearFaults).await?;
+    Ok(HttpResponse::Ok().json(schema::timers(&timers)))
+}
+
+#[get("/api/info")]
+async fn get_info(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "config": data.config,
+    }))
 }
 
 #[get("/api/events")]
@@ -137,9 +564,64 @@ async fn events(broadcaster: web::Data<Broadcaster>) -> impl Responder {
         .streaming(client)
 }
 
-async fn init_server(sender: mpsc::Sender<InputEvent>, receiver: mpsc::Receiver<OutputEvent>) -> std::io::Result<()> {
+#[get("/api/ws")]
+async fn ws(req: HttpRequest, body: web::Payload, data: web::Data<AppState>, broadcaster: web::Data<Broadcaster>) -> Result<HttpResponse, Error> {
+    let token = request_token(req.headers(), req.query_string());
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    let mut outgoing = broadcaster.new_ws_client();
+
+    // Push output events until the client goes away
+    let mut push_session = session.clone();
+    rt::spawn(async move {
+        while let Some(msg) = outgoing.recv().await {
+            if push_session.text(msg).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    rt::spawn(async move {
+        while let Some(Ok(msg)) = messages.next().await {
+            match msg {
+                Message::Text(text) => {
+                    // Checked for every message so changed PINs apply to open sessions too
+                    let role = data.auth.lock().unwrap().role(token.as_deref());
+                    let result = match serde_json::from_str::<InputEvent>(&text) {
+                        Ok(event) if role >= required_role_for_event(&event) => data.request(event).await.map(|_| ()),
+                        Ok(_) => Err(ApiError::Unauthorized),
+                        Err(e) => Err(ApiError::BadRequest(e.to_string())),
+                    };
+
+                    if let Err(e) = result {
+                        let error = json!({ "event": "error", "data": { "message": e.to_string(), "status": e.status_code().as_u16() } });
+                        if session.text(error.to_string()).await.is_err() {
+                            break;
+                        }
+                    }
+                },
+                Message::Ping(bytes) if session.pong(&bytes).await.is_err() => break,
+                Message::Close(_) => break,
+                _ => (),
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
+
+async fn init_server(sender: mpsc::Sender<Request>, receiver: mpsc::Receiver<OutputEvent>, history: Arc<Mutex<RunHistory>>, roster: Arc<Mutex<Roster>>, config: Config) -> std::io::Result<()> {
+    let bind_address = config.bind_address.clone();
+    let static_dir = config.static_dir.clone();
+    // Kept up to date from SyncSettings, loaded here so the API isn't open until the first sync
+    let auth = Arc::new(Mutex::new(Auth::from_settings(&Settings::load().unwrap_or_default())));
     let state = web::Data::new(AppState {
         sender: Mutex::new(sender),
+        history,
+        roster,
+        config,
+        auth: auth.clone(),
     });
 
     let broadcaster = Broadcaster::create();
@@ -147,64 +629,89 @@ async fn init_server(sender: mpsc::Sender<InputEvent>, receiver: mpsc::Receiver<
 
     spawn(move || {
         for event in receiver {
-            match event {
-                OutputEvent::SyncTimers(timers) => {
-                    let timer_objects: Vec<_> = timers.iter().enumerate().map(|(i, timer)| json!({
-                        "id": i,
-                        "startedAt": timer.started_at_datetime.map(|x| x.timestamp_millis()),
-                        "stoppedAt": timer.stopped_at_datetime.map(|x| x.timestamp_millis()),
-                        "countdown": timer.countdown_duration.as_secs(),
-                        "state": format!("{:?}", timer.get_state()),
-                        "formatted": timer.format(),
-                    })).collect();
-
-                    let payload = json!({
-                        "timers": timer_objects,
-                        "now": Utc::now().timestamp_millis(),
-                    });
-
-                    clone.send("syncTimers", &payload.to_string());
-                },
-                OutputEvent::SyncSettings(settings) => {
-                    let payload = json!({
-                        "settings": settings,
-                    });
+            if let OutputEvent::SyncSettings(ref settings) = event {
+                *auth.lock().unwrap() = Auth::from_settings(settings);
+            }
 
-                    clone.send("syncSettings", &payload.to_string());
-                },
-                #[allow(unreachable_patterns)]
-                _ => clone.send("outputEvent", &format!("{:?}", event))
-            };
+            let (name, payload) = schema::to_json(&event);
+            clone.send(name, &payload);
         }
     });
 
     let _ = HttpServer::new(move || {
         App::new()
+            .wrap_fn(|req, srv| {
+                let data = req.app_data::<web::Data<AppState>>().unwrap();
+                let role = data.auth.lock().unwrap().role(request_token(req.headers(), req.query_string()).as_deref());
+
+                if role < required_role(req.method(), req.match_info().as_str()) {
+                    let response = ApiError::Unauthorized.error_response();
+                    return Either::Left(ready(Ok(req.into_response(response).map_into_right_body())));
+                }
+
+                Either::Right(srv.call(req).map_ok(ServiceResponse::map_into_left_body))
+            })
             .app_data(state.clone())
             .app_data(broadcaster.clone())
+            .app_data(web::JsonConfig::default().error_handler(|e, _| ApiError::BadRequest(e.to_string()).into()))
+            .app_data(web::PathConfig::default().error_handler(|e, _| ApiError::NotFound(e.to_string()).into()))
             .service(start_timer)
+            .service(start_lane)
             .service(stop_timer)
             .service(stop_timers)
+            .service(split)
             .service(reset_timer)
+            .service(reset_lane)
             .service(request_sync)
             .service(events)
+            .service(get_info)
+            .service(ws)
             .service(enable_debug)
             .service(disable_debug)
             .service(set_countdown)
+            .service(set_lane_countdown)
+            .service(set_lane_mode)
+            .service(enable_require_buttons)
+            .service(disable_require_buttons)
+            .service(set_time_limit)
+            .service(set_lanes)
+            .service(set_lane_button)
+            .service(set_split_button)
+            .service(set_start_mat_button)
+            .service(set_role_buttons)
+            .service(set_key_bindings)
+            .service(set_serial)
+            .service(set_button_filters)
+            .service(enable_false_start)
+            .service(disable_false_start)
+            .service(set_pins)
             .service(delete_background)
             .service(upload_background)
             .service(toggle_display)
-            .service(fs::Files::new("/", "./client/dist").index_file("index.html"))
+            .service(list_runs)
+            .service(export_runs)
+            .service(delete_run)
+            .service(clear_runs)
+            .service(list_athletes)
+            .service(add_athlete)
+            .service(update_athlete)
+            .service(delete_athlete)
+            .service(assign_athlete)
+            .service(start_competition)
+            .service(end_competition)
+            .service(toggle_leaderboard)
+            .service(clear_faults)
+            .service(fs::Files::new("/", &static_dir).index_file("index.html"))
     })
-    .bind(("0.0.0.0", 8080))?
+    .bind(bind_address)?
     .run()
     .await;
 
     Ok(())
 }
 
-pub fn spawn_server(sender: mpsc::Sender<InputEvent>, receiver: mpsc::Receiver<OutputEvent>) -> JoinHandle<()> {
+pub fn spawn_server(sender: mpsc::Sender<Request>, receiver: mpsc::Receiver<OutputEvent>, history: Arc<Mutex<RunHistory>>, roster: Arc<Mutex<Roster>>, config: Config) -> JoinHandle<()> {
     spawn(|| {
-        rt::System::new().block_on(init_server(sender, receiver)).unwrap();
+        rt::System::new().block_on(init_server(sender, receiver, history, roster, config)).unwrap();
     })
 }